@@ -0,0 +1,132 @@
+//! Dead content: pages nothing links to and assets nothing references.
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::compile::BuildPlan;
+use crate::diagnostics::Diagnostic;
+use crate::graph::{EdgeKind, NodeKind, SiteGraph};
+
+/// A warning for every page no other page (or anything a page includes) links to.
+///
+/// `entry_points` match page output paths relative to the output directory; such
+/// pages are reached from outside the site and needn't be linked.
+pub fn orphan_pages(plan: &BuildPlan, entry_points: &[glob::Pattern]) -> Vec<Diagnostic> {
+    let graph = SiteGraph::from_plan(plan);
+    let linked = graph.edges
+        .iter()
+        .filter(|edge| edge.kind == EdgeKind::Link && edge.from != edge.to)
+        .map(|edge| edge.to.as_str())
+        .collect::<BTreeSet<_>>();
+    plan.pages
+        .iter()
+        .filter(|page| {
+            let output = page.output.strip_prefix(&plan.output_dir).unwrap_or(&page.output);
+            !entry_points.iter().any(|pattern| pattern.matches_path(output))
+        })
+        .filter(|page| !linked.contains(graph_id(plan, &page.source).as_str()))
+        .map(|page| {
+            Diagnostic::warning(format!("orphan page: nothing links to {}", graph_id(plan, &page.source)))
+                .with_file(&page.source)
+        })
+        .collect()
+}
+
+/// A warning for every file in `candidates` (bundle and asset files) that nothing
+/// references.
+pub fn unused_assets(plan: &BuildPlan, candidates: impl IntoIterator<Item=PathBuf>) -> Vec<Diagnostic> {
+    let graph = SiteGraph::from_plan(plan);
+    let referenced = graph.nodes
+        .iter()
+        .filter(|node| node.kind != NodeKind::Missing)
+        .map(|node| node.id.as_str())
+        .collect::<BTreeSet<_>>();
+    candidates
+        .into_iter()
+        .map(path_clean::clean)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|path| !referenced.contains(graph_id(plan, path).as_str()))
+        .map(|path| {
+            Diagnostic::warning(format!("unused asset: nothing references {}", graph_id(plan, &path)))
+                .with_file(&path)
+        })
+        .collect()
+}
+
+fn graph_id(plan: &BuildPlan, path: &Path) -> String {
+    crate::graph::node_id(&plan.project_root, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::PagePlan;
+    use crate::html_pass::system::Dependency;
+
+    fn page(root: &Path, name: &str, links: &[&str]) -> PagePlan {
+        PagePlan {
+            source: root.join(name),
+            output: root.join("output").join(name),
+            includes: Vec::default(),
+            layouts: Vec::default(),
+            cached: false,
+            dependencies: links
+                .iter()
+                .map(|link| Dependency { origin: root.join(name), target: PathBuf::from(link), is_internal: None })
+                .collect(),
+        }
+    }
+
+    fn messages(diagnostics: Vec<Diagnostic>) -> Vec<String> {
+        diagnostics.into_iter().map(|diagnostic| diagnostic.message).collect()
+    }
+
+    #[test]
+    fn pages_nothing_links_to_are_orphans() {
+        let root = PathBuf::from("/site");
+        let plan = BuildPlan {
+            project_root: root.clone(),
+            output_dir: root.join("output"),
+            pages: vec![
+                page(&root, "index.html", &["about.html"]),
+                page(&root, "about.html", &["about.html#team", "index.html"]),
+                page(&root, "draft.html", &["draft.html"]),
+                page(&root, "404.html", &[]),
+            ],
+            ..Default::default()
+        };
+        let entry_points = [glob::Pattern::new("404.html").unwrap()];
+        assert_eq!(messages(orphan_pages(&plan, &entry_points)), ["orphan page: nothing links to draft.html"]);
+    }
+
+    #[test]
+    fn entry_points_need_no_links() {
+        let root = PathBuf::from("/site");
+        let plan = BuildPlan {
+            project_root: root.clone(),
+            output_dir: root.join("output"),
+            pages: vec![page(&root, "index.html", &[])],
+            ..Default::default()
+        };
+        assert_eq!(messages(orphan_pages(&plan, &[])), ["orphan page: nothing links to index.html"]);
+        let entry_points = [glob::Pattern::new("index.html").unwrap()];
+        assert!(orphan_pages(&plan, &entry_points).is_empty());
+    }
+
+    #[test]
+    fn assets_nothing_references_are_unused() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        std::fs::write(root.join("logo.png"), "").unwrap();
+        std::fs::write(root.join("old.png"), "").unwrap();
+        let mut index = page(&root, "index.html", &[]);
+        index.dependencies.push(Dependency {
+            origin: root.join("index.html"),
+            target: PathBuf::from("logo.png"),
+            is_internal: Some(false),
+        });
+        let plan = BuildPlan { project_root: root.clone(), pages: vec![index], ..Default::default() };
+        let unused = unused_assets(&plan, [root.join("logo.png"), root.join("old.png")]);
+        assert_eq!(messages(unused), ["unused asset: nothing references old.png"]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Incremental build database, persisted between runs.
+//!
+//! For every page the cache records what it was compiled from — the page source, the
+//! template, every include and referenced asset — with a content hash of each. A page
+//! whose inputs all hash the same is neither parsed nor postprocessed again; its
+//! dependencies and diagnostics are replayed from the cache instead, so the build
+//! report matches a clean build.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::diagnostics::Diagnostic;
+use crate::html_pass::system::{Aggregator, Dependency};
+
+/// Bumped whenever the compiler's output could change for the same inputs.
+const CACHE_VERSION: &str = concat!("ssio-", env!("CARGO_PKG_VERSION"), "-14");
+const CACHE_FILE_NAME: &str = "build.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildCache {
+    pub version: String,
+    /// Hash of the compiler settings; any change invalidates every page.
+    pub config_fingerprint: String,
+    /// Hash of the site-wide link resolution state; any change invalidates every
+    /// page's output, though not its parsed form.
+    pub resolver_fingerprint: String,
+    /// Keyed by page source path.
+    pub pages: BTreeMap<PathBuf, CachedPage>,
+    /// Keyed by module entry source path.
+    pub modules: BTreeMap<PathBuf, CachedModule>,
+    /// Content hashes computed during this run, shared by every page.
+    #[serde(skip)]
+    hashes: Mutex<HashMap<PathBuf, Option<String>>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedPage {
+    pub output: PathBuf,
+    /// The template the page's input rule gave it.
+    pub template: Option<PathBuf>,
+    /// The layouts the page was baked into, innermost first.
+    pub layouts: Vec<PathBuf>,
+    /// The responsive image variants the page uses.
+    pub images: Vec<crate::images::ImageVariant>,
+    /// The page's Content Security Policy, when the build gives pages one.
+    pub csp: Option<String>,
+    /// Every file the page was compiled from, with its content hash (`None` when
+    /// the file was missing, e.g. a broken include).
+    pub inputs: BTreeMap<PathBuf, Option<String>>,
+    pub source_dependencies: Vec<Dependency>,
+    pub static_dependencies: Vec<Dependency>,
+    pub implicit_dependencies: Vec<Dependency>,
+    pub module_scripts: Vec<Dependency>,
+    /// Diagnostics from loading the page.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Diagnostics from postprocessing the page.
+    pub output_diagnostics: Vec<Diagnostic>,
+}
+
+/// A bundled module entry (see [`crate::modules`]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedModule {
+    pub output: PathBuf,
+    /// Every file the bundle was built from, with its content hash.
+    pub inputs: BTreeMap<PathBuf, Option<String>>,
+}
+
+impl BuildCache {
+    /// Loads the cache from `cache_dir`; a missing, unreadable or outdated cache is
+    /// treated as empty.
+    pub fn load(cache_dir: impl AsRef<Path>) -> Self {
+        std::fs::read_to_string(cache_dir.as_ref().join(CACHE_FILE_NAME))
+            .ok()
+            .and_then(|text| serde_json::from_str::<BuildCache>(&text).ok())
+            .filter(|cache| cache.version == CACHE_VERSION)
+            .unwrap_or_else(|| BuildCache { version: String::from(CACHE_VERSION), ..Default::default() })
+    }
+    pub fn save(&self, cache_dir: impl AsRef<Path>) -> std::io::Result<()> {
+        let cache_dir = cache_dir.as_ref();
+        std::fs::create_dir_all(cache_dir)?;
+        let text = serde_json::to_string(self).map_err(std::io::Error::other)?;
+        std::fs::write(cache_dir.join(CACHE_FILE_NAME), text)
+    }
+    /// Whether every input of a cached page is unchanged.
+    pub fn is_fresh(&self, page: &CachedPage) -> bool {
+        self.inputs_unchanged(&page.inputs)
+    }
+    /// Whether every input of a cached bundle is unchanged, and the bundle is still
+    /// on disk.
+    pub fn is_module_fresh(&self, module: &CachedModule) -> bool {
+        module.output.exists() && self.inputs_unchanged(&module.inputs)
+    }
+    fn inputs_unchanged(&self, inputs: &BTreeMap<PathBuf, Option<String>>) -> bool {
+        inputs
+            .iter()
+            .all(|(path, hash)| &self.hash_file(path) == hash)
+    }
+    /// The SHA-256 of a file's contents, or `None` if it can't be read.
+    pub fn hash_file(&self, path: &Path) -> Option<String> {
+        if let Some(hash) = self.hashes.lock().unwrap().get(path) {
+            return hash.clone()
+        }
+        let hash = std::fs::read(path).ok().map(|bytes| hex_digest(&bytes));
+        self.hashes.lock().unwrap().insert(path.to_path_buf(), hash.clone());
+        hash
+    }
+}
+
+impl CachedPage {
+    /// The aggregator the page produced when it was loaded.
+    pub fn aggregator(&self) -> Aggregator {
+        Aggregator {
+            source_dependencies: self.source_dependencies.iter().cloned().collect(),
+            static_dependencies: self.static_dependencies.iter().cloned().collect(),
+            implicit_dependencies: self.implicit_dependencies.iter().cloned().collect(),
+            module_scripts: self.module_scripts.iter().cloned().collect(),
+            diagnostics: self.diagnostics.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Hashes the `Debug` rendering of a value; only meaningful for values whose
+/// rendering is deterministic (no hash maps or sets).
+pub fn fingerprint(value: &impl Debug) -> String {
+    hex_digest(format!("{value:?}").as_bytes())
+}
+
+pub(crate) fn hex_digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached_page(inputs: BTreeMap<PathBuf, Option<String>>) -> CachedPage {
+        CachedPage {
+            output: PathBuf::from("output/index.html"),
+            template: None,
+            layouts: Vec::default(),
+            images: Vec::default(),
+            csp: None,
+            inputs,
+            source_dependencies: Vec::default(),
+            static_dependencies: Vec::default(),
+            implicit_dependencies: Vec::default(),
+            module_scripts: Vec::default(),
+            diagnostics: Vec::default(),
+            output_diagnostics: Vec::default(),
+        }
+    }
+
+    #[test]
+    fn missing_cache_loads_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BuildCache::load(dir.path().join("missing"));
+        assert_eq!(cache.version, CACHE_VERSION);
+        assert!(cache.pages.is_empty());
+        assert!(cache.modules.is_empty());
+    }
+
+    #[test]
+    fn saved_cache_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = BuildCache::load(dir.path());
+        cache.config_fingerprint = String::from("config");
+        cache.pages.insert(PathBuf::from("index.html"), cached_page(BTreeMap::default()));
+        cache.save(dir.path()).unwrap();
+        let loaded = BuildCache::load(dir.path());
+        assert_eq!(loaded.config_fingerprint, "config");
+        assert_eq!(loaded.pages.keys().collect::<Vec<_>>(), [Path::new("index.html")]);
+    }
+
+    #[test]
+    fn outdated_cache_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = BuildCache::load(dir.path());
+        cache.version = String::from("ssio-0.0.0-0");
+        cache.pages.insert(PathBuf::from("index.html"), cached_page(BTreeMap::default()));
+        cache.save(dir.path()).unwrap();
+        let loaded = BuildCache::load(dir.path());
+        assert_eq!(loaded.version, CACHE_VERSION);
+        assert!(loaded.pages.is_empty());
+    }
+
+    #[test]
+    fn page_is_fresh_until_an_input_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("index.html");
+        let missing = dir.path().join("missing.html");
+        std::fs::write(&source, "<p>one</p>").unwrap();
+        let cache = BuildCache::default();
+        let page = cached_page(BTreeMap::from([
+            (source.clone(), cache.hash_file(&source)),
+            (missing.clone(), cache.hash_file(&missing)),
+        ]));
+        assert!(BuildCache::default().is_fresh(&page));
+        std::fs::write(&source, "<p>two</p>").unwrap();
+        assert!(!BuildCache::default().is_fresh(&page));
+        std::fs::write(&source, "<p>one</p>").unwrap();
+        std::fs::write(&missing, "").unwrap();
+        assert!(!BuildCache::default().is_fresh(&page));
+    }
+
+    #[test]
+    fn fingerprint_follows_the_value() {
+        assert_eq!(fingerprint(&("a", 1)), fingerprint(&("a", 1)));
+        assert_ne!(fingerprint(&("a", 1)), fingerprint(&("a", 2)));
+    }
+}
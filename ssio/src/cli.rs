@@ -1,8 +1,12 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use clap::{Parser, Subcommand};
-use pretty_tree::PrettyTreePrinter;
 
-use crate::compile::Compiler;
+use crate::compile::{BuildReport, Compiler};
+use crate::csp::{ContentSecurityPolicy, CspOutput};
+use crate::modules::ModuleBundling;
+use crate::diagnostics::Diagnostic;
+use crate::html::ParserBackend;
+use crate::manifest::{BuildOptions, ProjectManifest};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -15,6 +19,14 @@ pub struct Cli {
 pub enum Command {
     Compile(CompileCli),
     Build(BuildCli),
+    /// Parse files with every parser backend and report any differences.
+    CheckParsers(CheckParsersCli),
+    /// Delete files in the output directory that the last build didn't produce.
+    Prune(PruneCli),
+    /// Create a starter project in the given directory.
+    Init(InitCli),
+    /// Print the site's dependency graph (pages, templates, includes, assets and links).
+    Graph(GraphCli),
 }
 
 #[derive(Parser, Debug)]
@@ -33,18 +45,102 @@ pub struct CompileCli {
     /// The project root.
     #[arg(long)]
     root: PathBuf,
-    /// Pretty-print HTML(5) files (more pretty); default value is true.
+    /// Pretty-print HTML(5) files (more pretty); default value is true, or false with
+    /// `--csp`.
     #[arg(long)]
     pretty_print: Option<bool>,
+    /// The HTML parser backend.
+    #[arg(long, value_enum, default_value_t)]
+    parser: ParserBackend,
+    /// Embed images and fonts up to this many bytes as data URIs.
+    #[arg(long)]
+    inline_threshold: Option<u64>,
+    /// Add `integrity` attributes to local scripts and stylesheets.
+    #[arg(long)]
+    subresource_integrity: bool,
+    /// Give every page a Content Security Policy allowing its inline scripts and
+    /// styles, in a `<meta>` or in a `_headers` file.
+    #[arg(long, value_enum)]
+    csp: Option<CspOutput>,
+    /// Bundle local `<script type="module">`s with their imports.
+    #[arg(long)]
+    bundle_modules: bool,
+    /// Minify bundled modules; implies `--bundle-modules`.
+    #[arg(long)]
+    minify_modules: bool,
 }
 
 #[derive(Parser, Debug)]
 pub struct BuildCli {
     #[arg(long)]
     pub manifest: PathBuf,
-    /// Pretty-print HTML(5) files (more pretty); default value is true.
+    /// Pretty-print HTML(5) files (more pretty); default value is true, or false with
+    /// `[csp]`.
     #[arg(long)]
     pretty_print: Option<bool>,
+    /// The HTML parser backend; overrides the manifest's `parser` setting.
+    #[arg(long, value_enum)]
+    parser: Option<ParserBackend>,
+    /// Compile every page without reading or writing the build cache; stale outputs
+    /// are still pruned.
+    #[arg(long)]
+    no_cache: bool,
+    /// Wipe the output directory before building.
+    #[arg(long)]
+    clean: bool,
+    /// Print what the build would write without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+    /// How `--dry-run` prints the build plan.
+    #[arg(long, value_enum, default_value_t)]
+    format: PlanFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum PlanFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Parser, Debug)]
+pub struct PruneCli {
+    #[arg(long)]
+    pub manifest: PathBuf,
+    /// List the files that would be deleted without deleting them.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct GraphCli {
+    #[arg(long)]
+    pub manifest: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    format: GraphFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum GraphFormat {
+    #[default]
+    Dot,
+    Json,
+}
+
+#[derive(Parser, Debug)]
+pub struct InitCli {
+    /// The directory to create the project in; created if missing.
+    pub dir: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+pub struct CheckParsersCli {
+    /// Check every HTML file under the manifest's project root (excluding the output directory).
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+    /// Additional file paths or unix style glob patterns to check.
+    #[arg(long, num_args = 1..)]
+    input: Vec<String>,
 }
 
 impl Cli {
@@ -52,6 +148,10 @@ impl Cli {
         match self.command {
             Command::Compile(compile_cli) => compile_cli.execute(),
             Command::Build(build_cli) => build_cli.execute(),
+            Command::CheckParsers(check_cli) => check_cli.execute(),
+            Command::Prune(prune_cli) => prune_cli.execute(),
+            Command::Init(init_cli) => init_cli.execute(),
+            Command::Graph(graph_cli) => graph_cli.execute(),
         }
     }
 }
@@ -61,30 +161,154 @@ impl CompileCli {
         let input_paths = crate::path_utils::resolve_file_path_paterns(&self.input)
             .unwrap()
             .into_iter()
-            .map(|path| {
-                crate::compile::InputRule {
-                    source: path,
-                    target: None,
-                }
-            })
+            .map(crate::compile::InputRule::new)
             .collect();
-        let compiler = Compiler {
-            project_root: self.root.clone(),
-            input_paths,
-            template_path: self.template.clone(),
-            output_dir: self.output.clone(),
-            pretty_print: self.pretty_print.unwrap_or(true),
-            bundles: Default::default(),
-        };
-        compiler.run();
+        let mut compiler = Compiler::new(&self.root, &self.output);
+        compiler.input_paths = input_paths;
+        compiler.template_path = self.template.clone();
+        compiler.pretty_print = self.pretty_print.unwrap_or(self.csp.is_none());
+        compiler.parser_backend = self.parser;
+        // Only the record of outputs, for pruning; every page is compiled.
+        compiler.cache_dir = Some(self.root.join(crate::manifest::CACHE_DIR_NAME));
+        compiler.incremental = false;
+        compiler.inline_threshold = self.inline_threshold;
+        compiler.subresource_integrity = self.subresource_integrity;
+        compiler.csp = self.csp.map(ContentSecurityPolicy::new);
+        compiler.modules = (self.bundle_modules || self.minify_modules).then_some(ModuleBundling { minify: self.minify_modules });
+        finish(compiler.run());
     }
 }
 
 impl BuildCli {
     pub fn execute(self) {
         let manifest_dir = self.manifest.parent().unwrap();
-        let manifest = crate::manifest::load_project_manifest(&self.manifest).unwrap();
-        manifest.execute(manifest_dir, self.pretty_print);
+        let manifest = load_manifest(&self.manifest);
+        let options = BuildOptions {
+            pretty_print: self.pretty_print,
+            parser: self.parser,
+            no_cache: self.no_cache,
+            clean: self.clean,
+            dry_run: self.dry_run,
+        };
+        let result = manifest.execute(manifest_dir, &options);
+        if let (true, Ok(report)) = (self.dry_run, result.as_ref()) {
+            match self.format {
+                PlanFormat::Text => report.plan.print(),
+                PlanFormat::Json => println!("{}", serde_json::to_string_pretty(&report.plan).unwrap()),
+            }
+        }
+        finish(result);
+    }
+}
+
+/// Loads a manifest, exiting with a failure status if it can't be read or parsed.
+fn load_manifest(path: &Path) -> ProjectManifest {
+    crate::manifest::load_project_manifest(path).unwrap_or_else(|error| {
+        Diagnostic::error(error.to_string()).emit();
+        std::process::exit(1);
+    })
+}
+
+/// Prints the build's diagnostics, exiting with a failure status on errors.
+fn finish(result: Result<BuildReport, Box<dyn std::error::Error>>) {
+    match result {
+        Ok(report) => {
+            for path in report.removed.iter() {
+                println!("🗑️ removed {}", path.display());
+            }
+            report.emit_diagnostics();
+            if report.has_errors() {
+                std::process::exit(1);
+            }
+        }
+        Err(error) => {
+            Diagnostic::error(error.to_string()).emit();
+            std::process::exit(1);
+        }
+    }
+}
+
+impl PruneCli {
+    pub fn execute(self) {
+        let manifest_dir = self.manifest.parent().unwrap();
+        let manifest = load_manifest(&self.manifest);
+        let compiler = manifest.compiler(manifest_dir);
+        let cache_dir = compiler.cache_dir.as_ref().unwrap();
+        let produced = crate::prune::load_output_record(cache_dir);
+        if produced.is_empty() {
+            Diagnostic::error("no record of a previous build; run `ssio build` first").emit();
+            std::process::exit(1);
+        }
+        let orphaned = crate::prune::orphaned_outputs(&compiler.output_dir, &produced);
+        for path in orphaned.iter() {
+            let verb = if self.dry_run { "would remove" } else { "removed" };
+            println!("🗑️ {verb} {}", path.display());
+        }
+        if self.dry_run {
+            return
+        }
+        if let Err(error) = crate::prune::remove_outputs(&compiler.output_dir, &orphaned) {
+            Diagnostic::error(error.to_string()).emit();
+            std::process::exit(1);
+        }
+    }
+}
+
+impl GraphCli {
+    pub fn execute(self) {
+        let manifest_dir = self.manifest.parent().unwrap();
+        let manifest = load_manifest(&self.manifest);
+        let options = BuildOptions { dry_run: true, ..Default::default() };
+        let result = manifest.execute(manifest_dir, &options);
+        if let Ok(report) = result.as_ref() {
+            let graph = crate::graph::SiteGraph::from_plan(&report.plan);
+            match self.format {
+                GraphFormat::Dot => print!("{}", graph.to_dot()),
+                GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&graph).unwrap()),
+            }
+        }
+        finish(result);
+    }
+}
+
+impl InitCli {
+    pub fn execute(self) {
+        let result = crate::scaffold::init_project(&self.dir).and_then(|created| {
+            for path in created.iter() {
+                println!("created {}", path.display());
+            }
+            crate::scaffold::verify_project(&self.dir)
+        });
+        match result {
+            Ok(_) => println!("✅ {} builds; run `ssio build --manifest {}`", self.dir.display(), self.dir.join("site.toml").display()),
+            Err(error) => {
+                Diagnostic::error(error.to_string()).emit();
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+impl CheckParsersCli {
+    pub fn execute(self) {
+        let mut files = crate::path_utils::resolve_file_path_paterns(&self.input).unwrap();
+        if let Some(manifest_path) = self.manifest.as_ref() {
+            let manifest_dir = manifest_path.parent().unwrap();
+            let manifest = load_manifest(manifest_path);
+            let root = manifest.project_root(manifest_dir);
+            let output_dir = root.join(&manifest.output_dir);
+            let pattern = root.join("**/*.html").to_string_lossy().to_string();
+            let project_files = crate::path_utils::resolve_file_path_paterns(&[pattern])
+                .unwrap()
+                .into_iter()
+                .filter(|path| !path.starts_with(&output_dir));
+            files.extend(project_files);
+        }
+        let report = crate::parser_check::check_parsers(&files);
+        report.print();
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
     }
 }
 
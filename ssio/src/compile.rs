@@ -1,31 +1,78 @@
-use std::{collections::{HashMap, HashSet}, path::PathBuf};
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, path::{Path, PathBuf}};
 
-// use pretty_tree::PrettyTreePrinter;
+use rayon::prelude::*;
+use serde::Serialize;
 
-use pretty_tree::PrettyTreePrinter;
-use serde::Deserialize;
-
-use crate::{html::ParserMode, html_pass::{postprocess::PostprocessEnvironment, system::{Aggregator, Dependency}}};
-use crate::dependency_tracking::resolve_virtual_paths::{PathResolver, VirtualPathContext};
+use crate::build_cache::{BuildCache, CachedModule, CachedPage};
+use crate::csp::{ContentSecurityPolicy, CspOutput};
+use crate::modules::ModuleBundling;
+use crate::dependency_tracking::data::UrlAttribute;
+use crate::diagnostics::Diagnostic;
+use crate::html::Html;
+use crate::{html::{ParserBackend, ParserMode}, html_pass::{load::ParseCache, postprocess::PostprocessEnvironment, system::{Aggregator, Dependency, Scope, State}}};
+use crate::dependency_tracking::resolve_virtual_paths::PathResolver;
 // use crate::process::{process_html_file, Dependency, OutputContext, SiteLink};
 
+/// Compiles a set of pages into an output directory.
+///
+/// Paths may be absolute or relative to the working directory; sources, the template
+/// and bundle locations are expected to live under `project_root`. They are made
+/// absolute before the build starts, so the working directory is never consulted again.
+/// Start from [`Compiler::new`] and set the fields that differ; more are added as the
+/// compiler grows.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct Compiler {
     pub project_root: PathBuf,
     pub template_path: Option<PathBuf>,
     pub input_paths: Vec<InputRule>,
     pub output_dir: PathBuf,
+    /// Pretty-print pages; ignored (with a warning) when [`Compiler::csp`] is set,
+    /// since reformatted inline scripts and styles would no longer match their hashes.
     pub pretty_print: bool,
     pub bundles: Vec<BundleRule>,
+    pub parser_backend: ParserBackend,
+    /// Where the incremental build database and the record of the last build's
+    /// outputs live; `None` compiles every page on every run and never prunes.
+    pub cache_dir: Option<PathBuf>,
+    /// Reuse pages compiled by the previous build. When false every page is compiled
+    /// and the build database is neither read nor written, but outputs are still
+    /// recorded and stale ones pruned.
+    pub incremental: bool,
+    /// Plan the build (see [`BuildReport::plan`]) without writing anything.
+    pub dry_run: bool,
+    /// Images and fonts up to this many bytes, referenced by `<img src>` or a style's
+    /// `url()`, are embedded as data URIs (see [`crate::inline_assets`]).
+    pub inline_threshold: Option<u64>,
+    /// Add `integrity` attributes to local scripts and stylesheets (see
+    /// [`crate::integrity`]).
+    pub subresource_integrity: bool,
+    /// Give every page a Content Security Policy allowing its inline scripts and
+    /// styles (see [`crate::csp`]).
+    pub csp: Option<ContentSecurityPolicy>,
+    /// Bundle local `<script type="module">`s with their imports (see
+    /// [`crate::modules`]).
+    pub modules: Option<ModuleBundling>,
+    /// URL attributes tracked on top of the built-in ones, typically those of custom
+    /// elements (see [`crate::dependency_tracking::data::url_kind`]).
+    pub url_attributes: Vec<UrlAttribute>,
+    /// Where the output directory is published, such as `https://example.com/`. URLs
+    /// crawlers need absolute (see [`crate::dependency_tracking::data::UrlKind`]) are
+    /// written from it; without one they're left as written.
+    pub site_url: Option<String>,
 }
 
 /// Input file with optional rewrite rule
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct InputRule {
     /// Input file path
     pub source: PathBuf,
     /// Desired output path
     pub target: Option<PathBuf>,
+    /// The page's template, instead of [`Compiler::template_path`]; the page itself
+    /// may still pick another (see [`crate::layout`]).
+    pub template: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,49 +80,298 @@ pub struct BundleRule {
     pub location: PathBuf,
 }
 
+/// What a build produced.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct BuildReport {
+    /// Every compiled page, in input order.
+    pub pages: Vec<PageReport>,
+    /// Every symlinked asset and bundle, and every responsive image variant.
+    pub assets: Vec<AssetReport>,
+    /// Outputs deleted because the build no longer produces them, and dangling
+    /// symlinks.
+    pub removed: Vec<PathBuf>,
+    /// What the build writes (or, in a dry run, would write).
+    pub plan: BuildPlan,
+    /// Warnings and errors, deduplicated, in the order they were found.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PageReport {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    /// False when the file on disk was already up to date.
+    pub written: bool,
+    /// True when the page's inputs were unchanged and it wasn't compiled at all.
+    pub cached: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetReport {
+    pub source: PathBuf,
+    pub output: PathBuf,
+}
+
+impl BuildReport {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|x| x.severity == crate::diagnostics::Severity::Error)
+    }
+    pub fn emit_diagnostics(&self) {
+        for diagnostic in self.diagnostics.iter() {
+            diagnostic.emit();
+        }
+    }
+    fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        if !self.diagnostics.contains(&diagnostic) {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+}
+
 impl Compiler {
-    pub fn run(&self) {
-        std::fs::create_dir_all(&self.output_dir).unwrap();
-        let template = self.template_path.as_ref().map(|path| {
-            match crate::html_pass::load::load_html_file(path, ParserMode::Document, &self.project_root) {
-                Ok(x) => x,
-                Err(error) => {
-                    eprintln!("Failed to read file: {path:?}");
-                    panic!("{error}")
-                }
+    /// A compiler with no template, inputs or bundles, pretty-printing its output.
+    pub fn new(project_root: impl Into<PathBuf>, output_dir: impl Into<PathBuf>) -> Self {
+        Compiler {
+            project_root: project_root.into(),
+            template_path: None,
+            input_paths: Vec::default(),
+            output_dir: output_dir.into(),
+            pretty_print: true,
+            bundles: Vec::default(),
+            parser_backend: ParserBackend::default(),
+            cache_dir: None,
+            incremental: true,
+            dry_run: false,
+            inline_threshold: None,
+            subresource_integrity: false,
+            csp: None,
+            modules: None,
+            url_attributes: Vec::default(),
+            site_url: None,
+        }
+    }
+    pub fn run(&self) -> Result<BuildReport, Box<dyn std::error::Error>> {
+        self.with_absolute_paths()?.build()
+    }
+    /// Returns a copy with every path made absolute and lexically cleaned.
+    fn with_absolute_paths(&self) -> std::io::Result<Compiler> {
+        let absolute = |path: &Path| std::path::absolute(path).map(path_clean::clean);
+        Ok(Compiler {
+            project_root: absolute(&self.project_root)?,
+            template_path: self.template_path.as_deref().map(absolute).transpose()?,
+            input_paths: self.input_paths
+                .iter()
+                .map(|rule| {
+                    Ok(InputRule {
+                        source: absolute(&rule.source)?,
+                        target: rule.target.clone(),
+                        template: rule.template.as_deref().map(absolute).transpose()?,
+                    })
+                })
+                .collect::<std::io::Result<Vec<_>>>()?,
+            output_dir: absolute(&self.output_dir)?,
+            pretty_print: self.pretty_print,
+            bundles: self.bundles
+                .iter()
+                .map(|bundle| Ok(BundleRule { location: absolute(&bundle.location)? }))
+                .collect::<std::io::Result<Vec<_>>>()?,
+            parser_backend: self.parser_backend,
+            cache_dir: self.cache_dir.as_deref().map(absolute).transpose()?,
+            incremental: self.incremental,
+            dry_run: self.dry_run,
+            inline_threshold: self.inline_threshold,
+            subresource_integrity: self.subresource_integrity,
+            csp: self.csp.clone(),
+            modules: self.modules.clone(),
+            url_attributes: self.url_attributes.clone(),
+            site_url: self.site_url.clone(),
+        })
+    }
+    /// The output location of a file under the project root, mirroring its position.
+    fn mirrored_output_path(&self, source: &Path) -> Option<PathBuf> {
+        source
+            .strip_prefix(&self.project_root)
+            .ok()
+            .map(|relative| path_clean::clean(self.output_dir.join(relative)))
+    }
+    /// Writes a page unless the file on disk already matches; returns whether it wrote.
+    fn write_page(&self, html: &crate::html::Html, out_path: &Path) -> std::io::Result<bool> {
+        let page_str = if self.pretty_prints() {
+            html.pretty_html_string()
+        } else {
+            let doctype = "<!DOCTYPE html>";
+            format!(
+                "{doctype}\n{}",
+                html.html_string(&Default::default()),
+            )
+        };
+        let should_write = std::fs::read_to_string(out_path)
+            .map(|current| {
+                current !=  page_str
+            })
+            .unwrap_or(true);
+        if should_write {
+            if let Some(parent) = out_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(out_path, page_str)?;
+        }
+        Ok(should_write)
+    }
+    /// Loads a page and bakes it into its layouts: the one it declares, or else
+    /// `template`, then each layout's parent in turn, hoisting head elements (see
+    /// [`crate::head`]) into the result. Returns the page and its layouts, innermost
+    /// first.
+    fn load_page(&self, source: &Path, template: Option<&Path>, parse_cache: &ParseCache) -> Result<(State<Html>, Vec<PathBuf>), String> {
+        let loader = Scope {
+            project_root: self.project_root.clone(),
+            source_path: source.to_path_buf(),
+            parser_backend: self.parser_backend,
+            inline_threshold: self.inline_threshold,
+            url_attributes: self.url_attributes.clone(),
+            site_url: self.site_url.clone(),
+            parse_cache: parse_cache.clone(),
+        };
+        let load = |path: &Path, mode: ParserMode| crate::html_pass::load::load_html_file(path, mode, &loader);
+        let mut page = load(source, ParserMode::fragment("div"))
+            .map_err(|error| format!("failed to read page {source:?}: {error}"))?;
+        let mut layout = page.value
+            .take_layout()
+            .map(|layout| crate::layout::resolve_layout(source, &layout, &self.project_root))
+            .or_else(|| template.map(Path::to_path_buf));
+        let mut layouts = Vec::<PathBuf>::new();
+        // Head elements of the page, then of each layout baked into a parent.
+        let mut hoisted = Vec::<Vec<Html>>::new();
+        while let Some(path) = layout {
+            if layouts.contains(&path) {
+                return Err(format!("layout cycle in page {source:?}: {layouts:?} leads back to {path:?}"))
+            }
+            let mut loaded = load(&path, ParserMode::Document)
+                .map_err(|error| format!("failed to read layout {path:?}: {error}"))?;
+            let parent = loaded.value
+                .take_layout()
+                .map(|parent| crate::layout::resolve_layout(&path, &parent, &self.project_root));
+            if parent.is_some() {
+                // Baked into its parent's `<content>`, so parsed like a page.
+                loaded = load(&path, ParserMode::fragment("div"))
+                    .map_err(|error| format!("failed to read layout {path:?}: {error}"))?;
+                loaded.value.take_layout();
             }
+            hoisted.push(crate::head::take_head_elements(&mut page.value));
+            page = crate::template::bake_template_content(loaded, page, true);
+            layouts.push(path);
+            layout = parent;
+        }
+        let component_assets = std::mem::take(&mut page.aggregator.component_assets);
+        // Outermost first, so the page's own elements win.
+        let page = page.map(|html| {
+            let html = hoisted
+                .into_iter()
+                .rev()
+                .fold(html, crate::head::inject_head);
+            crate::head::inject_component_assets(html, component_assets)
         });
+        Ok((page, layouts))
+    }
+    /// The template a rule's pages get unless they declare a layout.
+    fn rule_template<'a>(&'a self, rule: &'a InputRule) -> Option<&'a Path> {
+        rule.template.as_deref().or(self.template_path.as_deref())
+    }
+    /// Whether pages are pretty printed: never with a Content Security Policy, whose
+    /// hashes are taken before pages are printed.
+    fn pretty_prints(&self) -> bool {
+        self.pretty_print && self.csp.is_none()
+    }
+    /// The settings every page's loaded form depends on.
+    fn config_fingerprint(&self) -> String {
+        crate::build_cache::fingerprint(&(
+            &self.project_root,
+            &self.template_path,
+            &self.output_dir,
+            self.pretty_prints(),
+            self.parser_backend,
+            self.inline_threshold,
+            self.subresource_integrity,
+            &self.csp,
+            &self.modules,
+            &self.url_attributes,
+            &self.site_url,
+        ))
+    }
+    /// Every file a loaded page was compiled from, hashed.
+    fn page_inputs(&self, source: &Path, layouts: &[PathBuf], aggregator: &Aggregator, cache: &BuildCache) -> BTreeMap<PathBuf, Option<String>> {
+        std::iter::once(source.to_path_buf())
+            .chain(layouts.iter().cloned())
+            .chain(aggregator.static_dependencies.iter().map(Dependency::resolved_source_file_path))
+            .chain(aggregator.implicit_dependencies.iter().map(Dependency::resolved_source_file_path))
+            .map(|path| {
+                let hash = cache.hash_file(&path);
+                (path, hash)
+            })
+            .collect()
+    }
+    fn build(&self) -> Result<BuildReport, Box<dyn std::error::Error>> {
+        let mut report = BuildReport::default();
+        if self.pretty_print != self.pretty_prints() {
+            report.push_diagnostic(Diagnostic::warning(
+                "not pretty printing: it would reformat inline scripts and styles so they no longer match their Content Security Policy hashes"
+            ));
+        }
+        let parse_cache = ParseCache::default();
+        let incremental_cache_dir = self.cache_dir.as_ref().filter(|_| self.incremental);
+        let mut build_cache = incremental_cache_dir
+            .map(BuildCache::load)
+            .unwrap_or_default();
+        let previous_pages = std::mem::take(&mut build_cache.pages);
+        let previous_outputs = self.cache_dir
+            .as_ref()
+            .map(crate::prune::load_output_record)
+            .unwrap_or_default();
+        let previous_modules = std::mem::take(&mut build_cache.modules);
+        let config_fingerprint = self.config_fingerprint();
+        let config_unchanged = incremental_cache_dir.is_some() && build_cache.config_fingerprint == config_fingerprint;
+        build_cache.config_fingerprint = config_fingerprint;
+        // Pages are loaded in parallel; collecting keeps input order, so everything
+        // merged from them below is independent of scheduling.
         let page_contents = self.input_paths
-            .clone()
-            .into_iter()
+            .par_iter()
             .map(|rule| {
-                let source_io = crate::html_pass::load::load_html_file(
-                    &rule.source,
-                    ParserMode::fragment("div"),
-                    &self.project_root
-                ).unwrap();
-                let baked_io = template
-                    .clone()
-                    .map(|template| {
-                        crate::template::bake_template_content(template, source_io.clone(), true)
-                    })
-                    .unwrap_or_else(|| source_io.clone());
-                // baked_io.value.print_pretty_tree();
-                (rule.source, baked_io, rule.target)
+                let template = self.rule_template(rule);
+                let cached = previous_pages
+                    .get(&rule.source)
+                    .filter(|page| config_unchanged && page.template.as_deref() == template && build_cache.is_fresh(page));
+                let page = match cached {
+                    Some(cached) => LoadedPage::Cached(cached.clone()),
+                    None => {
+                        let (page, layouts) = self.load_page(&rule.source, template, &parse_cache)?;
+                        LoadedPage::Fresh(page, layouts)
+                    }
+                };
+                Ok((rule.clone(), page))
             })
-            .map(|(src_path, page, out_path)| {
-                let out_path = out_path
+            .collect::<Vec<Result<_, String>>>()
+            .into_iter()
+            .collect::<Result<Vec<_>, String>>()?
+            .into_iter()
+            .map(|(rule, page)| {
+                let src_path = rule.source.clone();
+                let out_path = rule.target
+                    .as_ref()
                     .map(|out| {
-                        self.output_dir.join(out)
+                        path_clean::clean(self.output_dir.join(out))
                     })
-                    .unwrap_or_else(|| {
-                        let out = src_path.strip_prefix(&self.project_root).unwrap();
-                        let out = out.to_path_buf();
-                        self.output_dir.join(out)
-                    });
-                (src_path, page, out_path)
+                    .or_else(|| self.mirrored_output_path(&src_path))
+                    .ok_or_else(|| format!("page {src_path:?} is outside the project root {:?}", self.project_root))?;
+                if !out_path.starts_with(&self.output_dir) {
+                    return Err(format!("page {src_path:?} would be written outside the output directory, to {out_path:?}"))
+                }
+                Ok((rule, page, out_path))
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, String>>()?;
         // let env = page_contents
         //     .iter()
         //     .map(|(_, x, _)| x.aggregator.clone())
@@ -83,10 +379,13 @@ impl Compiler {
         let env = Aggregator::flatten(
             page_contents
                 .iter()
-                .map(|(_, x, _)| x.aggregator.clone())
+                .map(|(_, x, _)| x.aggregator())
         );
         // println!("{env:#?}");
-        let static_dependencies = env.static_dependencies
+        for diagnostic in env.diagnostics.iter() {
+            report.push_diagnostic(diagnostic.clone());
+        }
+        let mut static_dependencies = env.static_dependencies
             .clone()
             .into_iter()
             .filter(|x| {
@@ -99,65 +398,192 @@ impl Compiler {
                 keep
             })
             .collect::<Vec<_>>();
-        let site_links = env.source_dependencies
-            .clone()
-            .into_iter()
-            .map(|x| (x.normalized_target(), x))
-            .collect::<HashMap<_, _>>();
-        let asset_context = AssetContext {
-            project_directory: self.project_root.clone(),
-            output_directory: self.output_dir.clone(),
-        };
-        let asset_inputs = env.static_dependencies
+        static_dependencies.sort();
+        let mut modules = Vec::<ModuleOutput>::new();
+        if let Some(options) = self.modules.as_ref() {
+            let entries = env.module_scripts
+                .iter()
+                .map(Dependency::resolved_source_file_path)
+                .filter(|source| source.exists())
+                .collect::<BTreeSet<_>>();
+            let bundled = entries
+                .par_iter()
+                .map(|source| {
+                    let cached = previous_modules
+                        .get(source)
+                        .filter(|cached| config_unchanged && build_cache.is_module_fresh(cached));
+                    if let Some(cached) = cached {
+                        let module = ModuleOutput { source: source.clone(), output: cached.output.clone(), code: None };
+                        return Ok((module, cached.clone()))
+                    }
+                    let bundled = crate::modules::bundle(source, &self.project_root, options).map_err(Diagnostic::warning)?;
+                    let output = crate::modules::output_path(source, &self.project_root, &self.output_dir, &bundled.code);
+                    let inputs = bundled.inputs
+                        .iter()
+                        .map(|path| (path.clone(), build_cache.hash_file(path)))
+                        .collect();
+                    let module = ModuleOutput { source: source.clone(), output: output.clone(), code: Some(bundled.code) };
+                    Ok((module, CachedModule { output, inputs }))
+                })
+                .collect::<Vec<Result<_, Diagnostic>>>();
+            for result in bundled {
+                match result {
+                    Ok((module, cached)) => {
+                        build_cache.modules.insert(module.source.clone(), cached);
+                        modules.push(module);
+                    }
+                    // Published unbundled instead.
+                    Err(diagnostic) => report.push_diagnostic(diagnostic),
+                }
+            }
+        }
+        let is_bundled = |source: &Path| modules.iter().any(|module| module.source == source);
+        let mut asset_inputs = env.static_dependencies
             .iter()
             .filter(|x| !x.is_internal.unwrap_or(false))
-            .map(|x| {
-                InputRule {
-                    source: x.resolved_source_file_path(),
-                    target: Some(x.resolved_target_file_path(&self.output_dir))
-                }
+            .filter_map(|x| {
+                let source = x.resolved_source_file_path();
+                let target = self.mirrored_output_path(&source)?;
+                Some(InputRule { source, target: Some(target), template: None })
             })
             .map(|x| x.clean())
+            .filter(|x| !is_bundled(&x.source))
+            .chain(modules.iter().map(|module| {
+                InputRule { source: module.source.clone(), target: Some(module.output.clone()), template: None }
+            }))
             .collect::<Vec<_>>();
+        asset_inputs.sort_by(|left, right| (&left.source, &left.target).cmp(&(&right.source, &right.target)));
         // println!("{:#?}", self.bundles);
+        // Plan every asset symlink, then check the whole output plan for collisions
+        // before anything is written.
+        let mut planned_assets = Vec::<PlannedOutput>::new();
         for bundle in self.bundles.iter() {
             let source = bundle.location.clone();
-            let output = self.output_dir.join(&bundle.location);
+            let Some(output) = self.mirrored_output_path(&source) else {
+                report.push_diagnostic(Diagnostic::warning(format!("skipping bundle: {source:?} (outside the project root)")));
+                continue;
+            };
             // println!("BUNDLE: {source:?} => {output:?}");
             if !source.exists() {
-                eprintln!("⚠️ skipping bundle: {source:?} (file does not exist)");
+                report.push_diagnostic(Diagnostic::warning(format!("skipping bundle: {source:?} (file does not exist)")));
                 continue;
             }
-            let result = crate::symlink::create_relative_symlink(
-                &source,
-                &output
-            );
-            match result {
-                Ok(()) => (),
-                Err(error) => {
-                    // if let Some(error) = error.downcast_ref::<std::io::Error>() {}
-                    eprintln!("Failed to create symlink: {source:?} → {output:?}");
-                    eprintln!("{error}");
-                }
-            }
+            planned_assets.push(PlannedOutput { source, output, kind: OutputKind::Bundle });
         }
         for dependency in static_dependencies {
             let full_resolved_path = path_clean::clean(dependency.resolved_source_file_path());
-            let target_path = path_clean::clean(dependency.resolved_target_file_path(&self.output_dir));
             // println!("{:#?}", self.bundles);
             if !full_resolved_path.exists() {
-                eprintln!("⚠️ skipping bundle: {full_resolved_path:?} (file does not exist)");
+                report.push_diagnostic(Diagnostic::warning(format!("skipping asset: {full_resolved_path:?} (file does not exist)")));
                 continue;
             }
-            if dependency.should_ignore(&self.bundles, &asset_context) {
+            let Some(target_path) = self.mirrored_output_path(&full_resolved_path) else {
+                report.push_diagnostic(
+                    Diagnostic::warning(format!("skipping asset: {full_resolved_path:?} (outside the project root)"))
+                        .with_file(&dependency.origin)
+                );
+                continue;
+            };
+            if dependency.should_ignore(&self.bundles) || is_bundled(&full_resolved_path) {
                 // println!("IGNORING: {dependency:?}: {:?} => {:?}", full_resolved_path, target_path);
                 continue;
             }
-            // println!("{dependency:?}: {:?} => {:?}", full_resolved_path, target_path);
-            crate::symlink::create_relative_symlink(
-                &full_resolved_path,
-                &target_path
-            ).unwrap();
+            // The same file is often referenced from several pages.
+            let is_planned = planned_assets
+                .iter()
+                .any(|x| x.source == full_resolved_path && x.output == target_path);
+            if !is_planned {
+                planned_assets.push(PlannedOutput { source: full_resolved_path, output: target_path, kind: OutputKind::Asset });
+            }
+        }
+        for module in modules.iter() {
+            planned_assets.push(PlannedOutput { source: module.source.clone(), output: module.output.clone(), kind: OutputKind::Module });
+        }
+        // Responsive image variants are named after the original's contents, so
+        // they're known before any page is postprocessed.
+        let image_processor = crate::images::ImageProcessor::new(&self.output_dir);
+        for (_, page, _) in page_contents.iter() {
+            let variants = match page {
+                LoadedPage::Fresh(page, _) => image_processor.plan_page(&page.value, &self.project_root),
+                LoadedPage::Cached(cached) => cached.images.clone(),
+            };
+            for variant in variants {
+                if !planned_assets.iter().any(|x| x.output == variant.output) {
+                    planned_assets.push(PlannedOutput { source: variant.source, output: variant.output, kind: OutputKind::Image });
+                }
+            }
+        }
+        report.plan = BuildPlan {
+            project_root: self.project_root.clone(),
+            output_dir: self.output_dir.clone(),
+            template: self.template_path.clone(),
+            pages: page_contents
+                .iter()
+                .map(|(rule, page, output)| {
+                    let aggregator = page.aggregator();
+                    PagePlan {
+                        source: rule.source.clone(),
+                        output: output.clone(),
+                        includes: aggregator.static_dependencies
+                            .iter()
+                            .filter(|x| x.is_internal == Some(true))
+                            .map(Dependency::resolved_source_file_path)
+                            .collect::<BTreeSet<_>>()
+                            .into_iter()
+                            .collect(),
+                        layouts: page.layouts().to_vec(),
+                        cached: matches!(page, LoadedPage::Cached(_)),
+                        dependencies: sorted(
+                            aggregator.static_dependencies
+                                .into_iter()
+                                .chain(aggregator.implicit_dependencies)
+                                .chain(aggregator.source_dependencies)
+                        ),
+                    }
+                })
+                .collect(),
+            assets: planned_assets.clone(),
+            stale: Vec::default(),
+        };
+        let headers_path = self.csp
+            .as_ref()
+            .filter(|csp| csp.output == CspOutput::Headers)
+            .map(|_| self.output_dir.join(crate::csp::HEADERS_FILE_NAME));
+        let planned_outputs = report.plan
+            .outputs()
+            .map(|planned| planned.output)
+            .chain(headers_path.clone())
+            .collect::<BTreeSet<_>>();
+        report.plan.stale = crate::prune::stale_outputs(&self.output_dir, &previous_outputs, &planned_outputs);
+        let collisions = output_collisions(report.plan.outputs());
+        if !collisions.is_empty() {
+            for diagnostic in collisions {
+                report.push_diagnostic(diagnostic);
+            }
+            return Ok(report)
+        }
+        if self.dry_run {
+            return Ok(report)
+        }
+        std::fs::create_dir_all(&self.output_dir)?;
+        for PlannedOutput { source, output, kind } in planned_assets {
+            // println!("{kind:?}: {source:?} => {output:?}");
+            let result = match kind {
+                // Written by the pages that use them.
+                OutputKind::Image => continue,
+                OutputKind::Module => write_module(&modules, &output),
+                _ => crate::symlink::create_relative_symlink(&source, &output),
+            };
+            match (result, kind) {
+                (Ok(()), _) => {
+                    report.assets.push(AssetReport { source, output });
+                }
+                (Err(error), OutputKind::Bundle) => {
+                    // if let Some(error) = error.downcast_ref::<std::io::Error>() {}
+                    report.push_diagnostic(Diagnostic::error(format!("failed to create symlink: {source:?} → {output:?}: {error}")));
+                }
+                (Err(error), _) => return Err(error),
+            }
         }
         let path_resolver = PathResolver {
             source_input_rules: self.input_paths.clone(),
@@ -166,53 +592,302 @@ impl Compiler {
             output_dir: self.output_dir.clone(),
         };
         // println!("{path_resolver:#?}");
-        for (src_path, page, out_path) in page_contents {
-            assert!(out_path != src_path);
-            assert!(out_path.starts_with(&self.output_dir));
-            // let context = VirtualPathContext {
-            //     output_file_path: out_path.clone(),
-            //     origin_file_path: src_path.clone(),
-            //     resolver: path_resolver.clone(),
-            // };
-            // // println!("{context:#?}");
-            // let page_html = page.value.resolve_virtual_paths(&context);
-            let postprocess_environment = PostprocessEnvironment {
-                origin_file_path: src_path.clone(),
-                output_file_path: out_path.clone(),
-                resolver: path_resolver.clone(),
-            };
-            let finalized_html = page.value.postprocess(&postprocess_environment);
-
-            let page_str = if self.pretty_print {
-                finalized_html.pretty_html_string()
-            } else {
-                let doctype = "<!DOCTYPE html>";
-                format!(
-                    "{doctype}\n{}",
-                    finalized_html.html_string(&Default::default()),
-                )
-            };
-            let should_write = std::fs::read_to_string(&out_path)
-                .map(|current| {
-                    current !=  page_str
-                })
-                .unwrap_or(true);
-            if should_write {
-                if let Some(parent) = out_path.parent() {
-                    std::fs::create_dir_all(parent).unwrap();
+        let resolver_fingerprint = crate::build_cache::fingerprint(&path_resolver);
+        let resolver_unchanged = build_cache.resolver_fingerprint == resolver_fingerprint;
+        build_cache.resolver_fingerprint = resolver_fingerprint;
+        let compiled_pages = page_contents
+            .into_par_iter()
+            .map(|(rule, page, out_path)| {
+                let src_path = rule.source.clone();
+                assert!(out_path != src_path);
+                assert!(out_path.starts_with(&self.output_dir));
+                let page = match page {
+                    LoadedPage::Cached(cached) if resolver_unchanged && cached.output == out_path && out_path.exists() => {
+                        let page = PageReport { source: src_path, output: out_path, written: false, cached: true };
+                        return Ok((page, cached))
+                    }
+                    LoadedPage::Cached(_) => {
+                        self.load_page(&src_path, self.rule_template(&rule), &parse_cache).map_err(std::io::Error::other)?
+                    }
+                    LoadedPage::Fresh(page, layouts) => (page, layouts),
+                };
+                let (page, layouts) = page;
+                let inputs = self.page_inputs(&src_path, &layouts, &page.aggregator, &build_cache);
+                let postprocess_environment = PostprocessEnvironment {
+                    origin_file_path: src_path.clone(),
+                    output_file_path: out_path.clone(),
+                    resolver: path_resolver.clone(),
+                    subresource_integrity: self.subresource_integrity,
+                    content_security_policy: self.csp.is_some(),
+                    url_attributes: self.url_attributes.clone(),
+                    site_url: self.site_url.clone(),
+                };
+                let State { aggregator: loaded, value: html } = page;
+                let State { mut aggregator, value: finalized_html } = html.postprocess(&postprocess_environment);
+                let (finalized_html, images) = image_processor.process_page(finalized_html, &src_path, &out_path, &mut aggregator);
+                let csp = self.csp
+                    .as_ref()
+                    .map(|csp| (csp.output, csp.for_page(&aggregator.script_hashes, &aggregator.style_hashes)));
+                let finalized_html = match csp.clone() {
+                    Some((CspOutput::Meta, policy)) => crate::head::inject_head(finalized_html, vec![crate::csp::meta_element(policy)]),
+                    _ => finalized_html,
+                };
+                let written = self.write_page(&finalized_html, &out_path)?;
+                let cached = CachedPage {
+                    output: out_path.clone(),
+                    template: self.rule_template(&rule).map(Path::to_path_buf),
+                    layouts,
+                    images,
+                    csp: csp.map(|(_, policy)| policy),
+                    inputs,
+                    source_dependencies: sorted(loaded.source_dependencies),
+                    static_dependencies: sorted(loaded.static_dependencies),
+                    implicit_dependencies: sorted(loaded.implicit_dependencies),
+                    module_scripts: sorted(loaded.module_scripts),
+                    diagnostics: loaded.diagnostics,
+                    output_diagnostics: aggregator.diagnostics,
+                };
+                let page = PageReport { source: src_path, output: out_path, written, cached: false };
+                Ok((page, cached))
+            })
+            .collect::<Vec<std::io::Result<_>>>();
+        for compiled in compiled_pages {
+            let (page, cached) = compiled?;
+            for diagnostic in cached.output_diagnostics.iter() {
+                report.push_diagnostic(diagnostic.clone());
+            }
+            for variant in cached.images.iter() {
+                let asset = AssetReport { source: variant.source.clone(), output: variant.output.clone() };
+                if !report.assets.contains(&asset) {
+                    report.assets.push(asset);
                 }
-                std::fs::write(&out_path, page_str).unwrap();
             }
+            build_cache.pages.insert(page.source.clone(), cached);
+            report.pages.push(page);
+        }
+        if let Some(headers_path) = headers_path.as_ref() {
+            let policies = report.pages
+                .iter()
+                .filter_map(|page| {
+                    let policy = build_cache.pages.get(&page.source)?.csp.as_deref()?;
+                    Some((page.output.as_path(), policy))
+                });
+            let headers = crate::csp::headers_file(&self.output_dir, policies);
+            if std::fs::read_to_string(headers_path).ok().as_deref() != Some(headers.as_str()) {
+                std::fs::write(headers_path, headers)?;
+            }
+        }
+        let current_outputs = report.pages
+            .iter()
+            .map(|page| page.output.clone())
+            .chain(report.assets.iter().map(|asset| asset.output.clone()))
+            .chain(headers_path)
+            .collect::<BTreeSet<_>>();
+        let stale = crate::prune::stale_outputs(&self.output_dir, &previous_outputs, &current_outputs);
+        crate::prune::remove_outputs(&self.output_dir, &stale)?;
+        report.removed = stale;
+        if let Some(cache_dir) = self.cache_dir.as_ref() {
+            crate::prune::save_output_record(cache_dir, &current_outputs)?;
+        }
+        if let Some(cache_dir) = incremental_cache_dir {
+            build_cache.save(cache_dir)?;
         }
+        Ok(report)
     }
 }
 
-impl Dependency {
-    fn normalized_target(&self) -> PathBuf {
-        let origin_dir = self.origin.parent().unwrap();
-        let normalized_target = origin_dir.join(&self.target);
-        normalized_target
+/// Everything a build writes into the output directory.
+#[derive(Debug, Clone, Default, Serialize)]
+#[non_exhaustive]
+pub struct BuildPlan {
+    pub project_root: PathBuf,
+    pub output_dir: PathBuf,
+    pub template: Option<PathBuf>,
+    /// Every page, in input order.
+    pub pages: Vec<PagePlan>,
+    /// Every asset and bundle symlink, bundled module and image variant.
+    pub assets: Vec<PlannedOutput>,
+    /// Outputs of the previous build that will be deleted.
+    pub stale: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct PagePlan {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    /// Every file the page includes, directly or through another include or the
+    /// template.
+    pub includes: Vec<PathBuf>,
+    /// The layouts the page is baked into, innermost first.
+    pub layouts: Vec<PathBuf>,
+    /// True when the page's inputs are unchanged since the last build.
+    pub cached: bool,
+    /// Every include, asset and link found while loading the page, with the file it
+    /// was found in.
+    #[serde(skip)]
+    pub dependencies: Vec<Dependency>,
+}
+
+/// Something the build writes into the output directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedOutput {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub kind: OutputKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputKind {
+    /// A compiled page.
+    Page,
+    /// A symlink to a referenced file.
+    Asset,
+    /// A symlink to a whole bundle directory.
+    Bundle,
+    /// A module script bundled with its imports.
+    Module,
+    /// A resized variant of a responsive image.
+    Image,
+}
+
+impl BuildPlan {
+    /// Pages followed by assets.
+    pub fn outputs(&self) -> impl Iterator<Item=PlannedOutput> + '_ {
+        self.pages
+            .iter()
+            .map(|page| {
+                PlannedOutput { source: page.source.clone(), output: page.output.clone(), kind: OutputKind::Page }
+            })
+            .chain(self.assets.iter().cloned())
+    }
+    /// Prints one line per output, with paths relative to the project root.
+    pub fn print(&self) {
+        let show = |path: &Path| {
+            let path = path.strip_prefix(&self.project_root).unwrap_or(path);
+            path.display().to_string()
+        };
+        if let Some(template) = self.template.as_ref() {
+            println!("template {}", show(template));
+        }
+        for page in self.pages.iter() {
+            let cached = if page.cached { " (cached)" } else { "" };
+            println!("page {} → {}{cached}", show(&page.source), show(&page.output));
+            for layout in page.layouts.iter() {
+                println!("    layout {}", show(layout));
+            }
+            for include in page.includes.iter() {
+                println!("    include {}", show(include));
+            }
+        }
+        for asset in self.assets.iter() {
+            let kind = match asset.kind {
+                OutputKind::Bundle => "bundle",
+                OutputKind::Module => "module",
+                OutputKind::Image => "image",
+                _ => "asset",
+            };
+            println!("{kind} {} → {}", show(&asset.source), show(&asset.output));
+        }
+        for path in self.stale.iter() {
+            println!("remove {}", show(path));
+        }
+    }
+}
+
+/// An error for every output written by two different sources, or written inside a
+/// bundle's symlinked directory (and so into the bundle's sources).
+fn output_collisions(plan: impl IntoIterator<Item=PlannedOutput>) -> Vec<Diagnostic> {
+    let plan = plan.into_iter().collect::<Vec<_>>();
+    let mut diagnostics = Vec::new();
+    let mut claimed = HashMap::<&Path, &PlannedOutput>::new();
+    for planned in plan.iter() {
+        match claimed.get(planned.output.as_path()) {
+            Some(other) if other.source != planned.source => {
+                diagnostics.push(Diagnostic::error(format!(
+                    "output path collision: {:?} ({:?}) and {:?} ({:?}) both write {:?}",
+                    other.source, other.kind, planned.source, planned.kind, planned.output,
+                )));
+            }
+            // Two input rules matching the same page; they may differ in template.
+            Some(_) if planned.kind == OutputKind::Page => {
+                diagnostics.push(Diagnostic::error(format!(
+                    "output path collision: {:?} is matched by more than one input rule, each writing {:?}",
+                    planned.source, planned.output,
+                )));
+            }
+            Some(_) => (),
+            None => {
+                claimed.insert(&planned.output, planned);
+            }
+        }
+    }
+    let bundles = plan.iter().filter(|x| x.kind == OutputKind::Bundle);
+    for bundle in bundles {
+        let nested = plan
+            .iter()
+            .filter(|x| x.output != bundle.output && x.output.starts_with(&bundle.output));
+        for planned in nested {
+            diagnostics.push(Diagnostic::error(format!(
+                "output path collision: {:?} ({:?}) writes {:?}, inside the symlinked bundle {:?}",
+                planned.source, planned.kind, planned.output, bundle.source,
+            )));
+        }
     }
+    diagnostics
+}
+
+/// A bundled module entry; `code` is `None` when the bundle on disk is up to date.
+struct ModuleOutput {
+    source: PathBuf,
+    output: PathBuf,
+    code: Option<String>,
+}
+
+fn write_module(modules: &[ModuleOutput], output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let code = modules
+        .iter()
+        .find(|module| module.output == output)
+        .and_then(|module| module.code.as_ref());
+    if let Some(code) = code {
+        if let Some(parent) = output.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(output, code)?;
+    }
+    Ok(())
+}
+
+/// A page ready for postprocessing, or its cache entry when its inputs are unchanged.
+enum LoadedPage {
+    /// The page and its layouts.
+    Fresh(State<Html>, Vec<PathBuf>),
+    Cached(CachedPage),
+}
+
+impl LoadedPage {
+    fn aggregator(&self) -> Aggregator {
+        match self {
+            Self::Fresh(page, _) => page.aggregator.clone(),
+            Self::Cached(page) => page.aggregator(),
+        }
+    }
+    fn layouts(&self) -> &[PathBuf] {
+        match self {
+            Self::Fresh(_, layouts) => layouts,
+            Self::Cached(page) => &page.layouts,
+        }
+    }
+}
+
+fn sorted<T: Ord>(items: impl IntoIterator<Item=T>) -> Vec<T> {
+    let mut items = items.into_iter().collect::<Vec<_>>();
+    items.sort();
+    items
+}
+
+impl Dependency {
 }
 
 impl Dependency {
@@ -222,46 +897,173 @@ impl Dependency {
         let full = path_clean::clean(&full);
         full
     }
-    fn resolved_target_file_path(&self, output_dir: impl AsRef<std::path::Path>) -> PathBuf {
-        output_dir.as_ref().join(self.resolved_source_file_path())
-    }
-    // TODO: SUPER MESSY
-    fn should_ignore(&self, bundles: &[BundleRule], asset_context: &AssetContext) -> bool {
-        let project_directory = path_clean::clean(&asset_context.project_directory);
-        let target = path_clean::clean(&self.target);
-        let target = target
-            .strip_prefix(&project_directory)
-            .map(ToOwned::to_owned)
-            .unwrap_or_else(|_| target.clone());
-        let target = path_clean::clean(target);
+    /// Assets inside a bundle are published by the bundle's own symlink.
+    fn should_ignore(&self, bundles: &[BundleRule]) -> bool {
         let full_resolved_path = self.resolved_source_file_path();
-        let matches_bundle = bundles
+        bundles
             .iter()
-            .find(|bundle| {
-                let bundle_location = path_clean::clean(&bundle.location);
-                let bundle_path = bundle.location
-                    .strip_prefix(&project_directory)
-                    .unwrap_or_else(|_| &bundle_location);
-                let bundle_path = path_clean::clean(bundle_path);
-                let match_result_1 = target.starts_with(&bundle_path);
-                let match_result_2 = full_resolved_path.starts_with(&bundle_path);
-                match_result_1 || match_result_2
-            });
-        matches_bundle.is_some()
+            .any(|bundle| full_resolved_path.starts_with(&bundle.location))
     }
 }
 
 impl InputRule {
+    /// A page published at the path mirroring its source, with the compiler's template.
+    pub fn new(source: impl Into<PathBuf>) -> Self {
+        InputRule { source: source.into(), target: None, template: None }
+    }
     pub fn clean(self) -> Self {
         Self {
             source: path_clean::clean(&self.source),
             target: self.target.map(|target| path_clean::clean(&target)),
+            template: self.template.map(|template| path_clean::clean(&template)),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-struct AssetContext {
-    pub project_directory: PathBuf,
-    pub output_directory: PathBuf,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    /// Every file under `dir`, following symlinks, keyed by its path relative to `dir`.
+    fn read_tree(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(next) = pending.pop() {
+            for entry in std::fs::read_dir(next).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else {
+                    files.insert(path.strip_prefix(dir).unwrap().to_path_buf(), std::fs::read(&path).unwrap());
+                }
+            }
+        }
+        files
+    }
+
+    /// Runs the build, checks that it stopped before writing anything and returns its
+    /// error messages.
+    fn collisions(compiler: &Compiler) -> Vec<String> {
+        let report = compiler.run().unwrap();
+        assert!(!compiler.output_dir.exists(), "{:?}", report.diagnostics);
+        report.diagnostics
+            .into_iter()
+            .filter(|diagnostic| diagnostic.severity == crate::diagnostics::Severity::Error)
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    #[test]
+    fn targets_collide_after_normalization() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "a.html", "<p>a</p>");
+        write(root, "b.html", "<p>b</p>");
+        let mut compiler = Compiler::new(root, root.join("output"));
+        let mut renamed = InputRule::new(root.join("b.html"));
+        renamed.target = Some(PathBuf::from("drafts/../a.html"));
+        compiler.input_paths = vec![InputRule::new(root.join("a.html")), renamed];
+        let errors = collisions(&compiler);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].starts_with("output path collision"), "{errors:?}");
+        assert!(errors[0].ends_with(&format!("both write {:?}", root.join("output/a.html"))), "{errors:?}");
+    }
+
+    #[test]
+    fn globs_with_different_prefixes_collide() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "pages/index.html", "<p>home</p>");
+        write(root, "posts/index.html", "<p>posts</p>");
+        let manifest = toml::from_str::<crate::manifest::ProjectManifest>(r#"
+            [[globs]]
+            pattern = "pages/*.html"
+            strip_prefix = "pages/"
+            [[globs]]
+            pattern = "posts/*.html"
+            strip_prefix = "posts/"
+        "#).unwrap();
+        let errors = collisions(&manifest.compiler(root));
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].contains(&format!("{:?}", root.join("pages/index.html"))), "{errors:?}");
+        assert!(errors[0].contains(&format!("{:?}", root.join("posts/index.html"))), "{errors:?}");
+    }
+
+    #[test]
+    fn a_page_matched_by_two_rules_collides_with_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "index.html", "<p>home</p>");
+        write(root, "plain.html", "<content></content>");
+        write(root, "fancy.html", "<main><content></content></main>");
+        let mut compiler = Compiler::new(root, root.join("output"));
+        let mut plain = InputRule::new(root.join("index.html"));
+        plain.template = Some(root.join("plain.html"));
+        let mut fancy = InputRule::new(root.join("index.html"));
+        fancy.template = Some(root.join("fancy.html"));
+        compiler.input_paths = vec![plain, fancy];
+        let errors = collisions(&compiler);
+        assert_eq!(errors, [format!(
+            "output path collision: {:?} is matched by more than one input rule, each writing {:?}",
+            root.join("index.html"), root.join("output/index.html"),
+        )]);
+    }
+
+    #[test]
+    fn pages_inside_a_symlinked_bundle_collide() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "static/logo.svg", "<svg></svg>");
+        write(root, "static/about.html", "<p>about</p>");
+        let mut compiler = Compiler::new(root, root.join("output"));
+        compiler.bundles = vec![BundleRule { location: root.join("static") }];
+        compiler.input_paths = vec![InputRule::new(root.join("static/about.html"))];
+        let errors = collisions(&compiler);
+        assert_eq!(errors, [format!(
+            "output path collision: {:?} (Page) writes {:?}, inside the symlinked bundle {:?}",
+            root.join("static/about.html"), root.join("output/static/about.html"), root.join("static"),
+        )]);
+    }
+
+    #[test]
+    fn parallel_and_serial_builds_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "base.html", "<html><head><link rel=\"stylesheet\" href=\"site.css\"></head><body><include src=\"nav.html\"></include><content></content></body></html>");
+        write(root, "nav.html", "<nav><a href=\"pages/page0.html\">Home</a></nav>");
+        write(root, "site.css", "body { margin: 0 }");
+        let mut compiler = Compiler::new(root, root.join("output"));
+        compiler.template_path = Some(root.join("base.html"));
+        compiler.pretty_print = false;
+        for index in 0..12 {
+            let page = format!("pages/page{index}.html");
+            write(root, &page, &format!(
+                "<h1>Page {index}</h1>\n<include></include>\n<style>p {{ color: red }} @media ( {{</style>\n<a href=\"page{}.html\">next</a>",
+                (index + 1) % 12,
+            ));
+            compiler.input_paths.push(InputRule::new(root.join(page)));
+        }
+
+        let serial = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let serial_report = serial.install(|| compiler.run().unwrap());
+        let serial_output = read_tree(&root.join("output"));
+        std::fs::remove_dir_all(root.join("output")).unwrap();
+        let parallel = rayon::ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        for _ in 0..3 {
+            let report = parallel.install(|| compiler.run().unwrap());
+            assert_eq!(report.diagnostics, serial_report.diagnostics);
+            let pages = report.pages.iter().map(|page| &page.output).collect::<Vec<_>>();
+            assert_eq!(pages, serial_report.pages.iter().map(|page| &page.output).collect::<Vec<_>>());
+            assert_eq!(read_tree(&root.join("output")), serial_output);
+            std::fs::remove_dir_all(root.join("output")).unwrap();
+        }
+        assert_eq!(serial_report.diagnostics.len(), 24, "{:?}", serial_report.diagnostics);
+    }
 }
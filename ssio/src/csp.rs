@@ -0,0 +1,198 @@
+//! Content Security Policy: allowing each page's own inline scripts and styles.
+//!
+//! With a [`ContentSecurityPolicy`] set, postprocessing hashes every inline `<script>`
+//! and `<style>` of a page (see [`crate::html_pass::postprocess`]), and the page's
+//! policy is the configured one with those hashes added to its `script-src` and
+//! `style-src`. A directive the policy leaves out starts from its `default-src`. The
+//! policy goes into a `<meta http-equiv="Content-Security-Policy">` (replacing the
+//! page's own), or into a [`HEADERS_FILE_NAME`] file at the root of the output
+//! directory, the format Netlify and Cloudflare Pages read per-path headers from.
+//!
+//! `style` attributes and event handler attributes aren't hashed; a strict policy
+//! blocks them.
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::html::{Element, Html};
+
+pub const HEADERS_FILE_NAME: &str = "_headers";
+
+const DEFAULT_POLICY: &str = "default-src 'self'; object-src 'none'; base-uri 'self'";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContentSecurityPolicy {
+    /// Where each page's policy goes.
+    #[serde(default)]
+    pub output: CspOutput,
+    /// The directives every page starts from.
+    #[serde(default = "default_policy")]
+    pub policy: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CspOutput {
+    /// A `<meta http-equiv="Content-Security-Policy">` in each page's `<head>`.
+    #[default]
+    Meta,
+    /// One `_headers` file for the whole site.
+    Headers,
+}
+
+fn default_policy() -> String {
+    String::from(DEFAULT_POLICY)
+}
+
+impl ContentSecurityPolicy {
+    pub fn new(output: CspOutput) -> Self {
+        ContentSecurityPolicy { output, policy: default_policy() }
+    }
+    /// The policy for a page with these inline script and style hashes.
+    pub fn for_page(&self, script_hashes: &BTreeSet<String>, style_hashes: &BTreeSet<String>) -> String {
+        let mut directives = self.policy
+            .split(';')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        add_sources(&mut directives, "script-src", script_hashes);
+        add_sources(&mut directives, "style-src", style_hashes);
+        directives.join("; ")
+    }
+}
+
+/// Appends hash sources to a directive, creating it from `default-src` if needed.
+fn add_sources(directives: &mut Vec<String>, name: &str, hashes: &BTreeSet<String>) {
+    if hashes.is_empty() {
+        return
+    }
+    let sources = hashes.iter().map(|hash| format!(" '{hash}'")).collect::<String>();
+    if let Some(directive) = directives.iter_mut().find(|directive| directive_name(directive) == name) {
+        directive.push_str(&sources);
+        return
+    }
+    let fallback = directives
+        .iter()
+        .find(|directive| directive_name(directive) == "default-src")
+        .map(|directive| directive["default-src".len()..].to_string())
+        .unwrap_or_else(|| String::from(" 'self'"));
+    directives.push(format!("{name}{fallback}{sources}"));
+}
+
+fn directive_name(directive: &str) -> String {
+    directive.split_ascii_whitespace().next().unwrap_or_default().to_ascii_lowercase()
+}
+
+/// The page's inline code to hash, when the element is an inline `<script>` or a
+/// `<style>`.
+pub fn inline_code(tag: &str, attrs: &HashMap<String, String>, children: &[Html]) -> Option<String> {
+    let is_inline = tag.eq_ignore_ascii_case("style")
+        || (tag.eq_ignore_ascii_case("script") && !attrs.contains_key("src"));
+    if !is_inline || children.is_empty() {
+        return None
+    }
+    Html::Fragment(children.to_vec()).to_text().ok()
+}
+
+/// The `<meta>` carrying a page's policy, for [`crate::head::inject_head`].
+pub fn meta_element(policy: String) -> Html {
+    let attrs = HashMap::from([
+        (String::from("http-equiv"), String::from("Content-Security-Policy")),
+        (String::from("content"), policy),
+    ]);
+    Html::Element(Element { tag: String::from("meta"), attrs, children: Vec::default(), location: None })
+}
+
+/// The `_headers` file for every page's policy, by output path; a directory's
+/// `index.html` is also listed under the directory's own URL.
+pub fn headers_file<'a>(output_dir: &Path, pages: impl IntoIterator<Item=(&'a Path, &'a str)>) -> String {
+    let mut file = String::new();
+    for (output, policy) in pages {
+        let Ok(relative) = output.strip_prefix(output_dir) else {
+            continue
+        };
+        let url = format!("/{}", relative.to_string_lossy().replace('\\', "/"));
+        let mut urls = vec![url.clone()];
+        if let Some(directory) = url.strip_suffix("index.html") {
+            urls.insert(0, directory.to_string());
+        }
+        for url in urls {
+            file.push_str(&format!("{url}\n  Content-Security-Policy: {policy}\n"));
+        }
+    }
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(hashes: &[&str]) -> BTreeSet<String> {
+        hashes.iter().map(|hash| hash.to_string()).collect()
+    }
+
+    fn policy(policy: &str) -> ContentSecurityPolicy {
+        ContentSecurityPolicy { output: CspOutput::Meta, policy: String::from(policy) }
+    }
+
+    #[test]
+    fn pages_without_inline_code_get_the_configured_policy() {
+        let csp = ContentSecurityPolicy::new(CspOutput::Meta);
+        assert_eq!(csp.for_page(&BTreeSet::new(), &BTreeSet::new()), DEFAULT_POLICY);
+    }
+
+    #[test]
+    fn hashes_are_added_to_their_directives() {
+        let csp = policy("default-src 'self'; Script-Src 'self' https://cdn.example.com; style-src 'self';");
+        let page = csp.for_page(&hashes(&["sha384-a", "sha384-b"]), &hashes(&["sha384-c"]));
+        assert_eq!(page, "default-src 'self'; Script-Src 'self' https://cdn.example.com 'sha384-a' 'sha384-b'; style-src 'self' 'sha384-c'");
+    }
+
+    #[test]
+    fn missing_directives_start_from_default_src() {
+        let page = policy("default-src 'self' data:").for_page(&hashes(&["sha384-a"]), &hashes(&["sha384-b"]));
+        assert_eq!(page, "default-src 'self' data:; script-src 'self' data: 'sha384-a'; style-src 'self' data: 'sha384-b'");
+        let page = policy("img-src *").for_page(&hashes(&["sha384-a"]), &BTreeSet::new());
+        assert_eq!(page, "img-src *; script-src 'self' 'sha384-a'");
+    }
+
+    #[test]
+    fn headers_list_directory_indexes_under_their_own_url() {
+        let output_dir = Path::new("/site/output");
+        let pages = [
+            (Path::new("/site/output/index.html"), "default-src 'self'"),
+            (Path::new("/site/output/blog/post.html"), "script-src 'sha384-a'"),
+            (Path::new("/elsewhere/page.html"), "ignored"),
+        ];
+        assert_eq!(headers_file(output_dir, pages), concat!(
+            "/\n  Content-Security-Policy: default-src 'self'\n",
+            "/index.html\n  Content-Security-Policy: default-src 'self'\n",
+            "/blog/post.html\n  Content-Security-Policy: script-src 'sha384-a'\n",
+        ));
+    }
+
+    #[test]
+    fn a_policy_turns_off_pretty_printing() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("index.html"), "<html><head></head><body><script>go()</script></body></html>").unwrap();
+        let mut compiler = crate::compile::Compiler::new(root, root.join("output"));
+        compiler.input_paths = vec![crate::compile::InputRule::new(root.join("index.html"))];
+        compiler.csp = Some(ContentSecurityPolicy::new(CspOutput::Meta));
+        assert!(compiler.pretty_print);
+        let report = compiler.run().unwrap();
+        assert!(!report.has_errors(), "{:?}", report.diagnostics);
+        let warnings = report.diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("not pretty printing"), "{warnings:?}");
+        let output = std::fs::read_to_string(root.join("output/index.html")).unwrap();
+        let hash = crate::integrity::sha384(b"go()");
+        assert!(output.starts_with("<!DOCTYPE html>\n<meta "), "{output}");
+        assert!(output.contains(&format!("script-src 'self' '{hash}'")), "{output}");
+        assert!(output.contains("<script>go()</script>"), "{output}");
+    }
+}
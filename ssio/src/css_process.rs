@@ -1,21 +1,35 @@
 use lightningcss::printer::PrinterOptions;
-use lightningcss::stylesheet::{ParserOptions, StyleSheet};
-use lightningcss::traits::ToCss;
-use lightningcss::rules::CssRule;
-use lightningcss::values::length::LengthValue;
+use lightningcss::stylesheet::{ParserOptions, StyleAttribute, StyleSheet};
+use lightningcss::selector::{Component, Selector};
 use lightningcss::values::url::Url;
 use lightningcss::visit_types;
 use std::convert::Infallible;
 use std::path::PathBuf;
 use lightningcss::visitor::{Visit, VisitTypes, Visitor};
 
+use crate::dependency_tracking::resolve_virtual_paths::VirtualPathContext;
+use crate::diagnostics::{Diagnostic, SourceLocation};
 use crate::html_pass::postprocess::PostprocessEnvironment;
 use crate::html_pass::system::Scope;
 use crate::html_pass::system::Aggregator;
 use crate::html_pass::system::Dependency;
 
-pub fn pre_process(source_code: &str, scope: &Scope, aggregator: &mut Aggregator) -> String {
-    let mut stylesheet = StyleSheet::parse(source_code, ParserOptions::default()).unwrap();
+/// Virtualizes the stylesheet's local URLs and, for a `<style scoped>`, narrows every
+/// selector to elements with `scope_attribute`.
+pub fn pre_process(
+    source_code: &str,
+    location: Option<&SourceLocation>,
+    scope: &Scope,
+    scope_attribute: Option<&str>,
+    aggregator: &mut Aggregator,
+) -> String {
+    let mut stylesheet = match StyleSheet::parse(source_code, ParserOptions::default()) {
+        Ok(stylesheet) => stylesheet,
+        Err(error) => {
+            aggregator.diagnostics.push(parse_error_diagnostic(&error, location, &scope.source_path));
+            return source_code.to_string()
+        }
+    };
     
     let mut visitor = CssPreprocessVisitor {
         scope,
@@ -23,17 +37,31 @@ pub fn pre_process(source_code: &str, scope: &Scope, aggregator: &mut Aggregator
     };
     
     stylesheet.visit(&mut visitor ).unwrap();
+
+    if let Some(attribute) = scope_attribute {
+        stylesheet.visit(&mut ScopedSelectorVisitor { attribute }).unwrap();
+    }
     
     let res: lightningcss::stylesheet::ToCssResult = stylesheet.to_css(PrinterOptions { minify: false, ..Default::default() }).unwrap();
 
     res.code
 }
 
-pub fn post_process(source_code: &str, env: &PostprocessEnvironment) -> String {
-    let mut stylesheet = StyleSheet::parse(source_code, ParserOptions::default()).unwrap();
+pub fn post_process(
+    source_code: &str,
+    location: Option<&SourceLocation>,
+    env: &PostprocessEnvironment,
+    aggregator: &mut Aggregator,
+) -> String {
+    // Syntax errors were already reported when the stylesheet was preprocessed.
+    let Ok(mut stylesheet) = StyleSheet::parse(source_code, ParserOptions::default()) else {
+        return source_code.to_string()
+    };
     
     let mut visitor = CssPostprocessVisitor {
-        environment: env,
+        context: &env.virtual_path_context(),
+        location,
+        aggregator,
     };
     
     stylesheet.visit(&mut visitor ).unwrap();
@@ -43,6 +71,70 @@ pub fn post_process(source_code: &str, env: &PostprocessEnvironment) -> String {
     res.code
 }
 
+/// Virtualizes the local URLs of a `style` attribute. Declarations without a `url()`
+/// are left as written.
+pub fn pre_process_style_attribute(source_code: &str, scope: &Scope, aggregator: &mut Aggregator) -> String {
+    if !has_url(source_code) {
+        return source_code.to_string()
+    }
+    // The parser skips invalid declarations, as browsers do; anything worse is left
+    // as written.
+    let Ok(mut declarations) = StyleAttribute::parse(source_code, ParserOptions::default()) else {
+        return source_code.to_string()
+    };
+    declarations.visit(&mut CssPreprocessVisitor { scope, aggregator }).unwrap();
+    declarations.to_css(PrinterOptions { minify: true, ..Default::default() }).unwrap().code
+}
+
+/// Resolves the virtual URLs of a `style` attribute.
+pub fn post_process_style_attribute(
+    source_code: &str,
+    location: Option<&SourceLocation>,
+    context: &VirtualPathContext,
+    aggregator: &mut Aggregator,
+) -> String {
+    if !has_url(source_code) {
+        return source_code.to_string()
+    }
+    let Ok(mut declarations) = StyleAttribute::parse(source_code, ParserOptions::default()) else {
+        return source_code.to_string()
+    };
+    declarations.visit(&mut CssPostprocessVisitor { context, location, aggregator }).unwrap();
+    declarations.to_css(PrinterOptions { minify: true, ..Default::default() }).unwrap().code
+}
+
+fn has_url(source_code: &str) -> bool {
+    source_code.to_ascii_lowercase().contains("url(")
+}
+
+/// Locates a stylesheet syntax error relative to the `<style>` element it came from.
+/// The stylesheet is still published as written, so this is only a warning.
+fn parse_error_diagnostic(
+    error: &lightningcss::error::Error<lightningcss::error::ParserError>,
+    location: Option<&SourceLocation>,
+    file: &std::path::Path,
+) -> Diagnostic {
+    let error_location = location.map(|location| {
+        error.loc
+            .as_ref()
+            .map(|loc| content_location(location).offset_by(loc.line as usize, loc.column as usize))
+            .unwrap_or_else(|| location.clone())
+    });
+    Diagnostic::warning(format!("invalid CSS: {}", error.kind))
+        .with_file(file)
+        .with_location(error_location.as_ref())
+}
+
+/// Error positions are relative to the stylesheet text, which starts after the
+/// `<style ...>` tag at `location`. Only needed when reporting an error, so the
+/// source is read again rather than tracked for every element.
+fn content_location(location: &SourceLocation) -> SourceLocation {
+    std::fs::read_to_string(&location.file)
+        .ok()
+        .and_then(|source| crate::html_parser2::content_position(&source, location.position))
+        .map(|position| SourceLocation::new(location.file.clone(), position))
+        .unwrap_or_else(|| location.clone())
+}
 
 struct CssPreprocessVisitor<'a> {
     scope: &'a Scope,
@@ -61,6 +153,10 @@ impl<'a, 'i> Visitor<'i> for CssPreprocessVisitor<'a> {
         if crate::path_utils::is_external_url(&url_str) {
             return Ok(())
         }
+        if let Some(data_uri) = crate::inline_assets::try_data_uri(&url_str, self.scope, self.aggregator) {
+            url.url = data_uri.into();
+            return Ok(())
+        }
         let virtual_src = crate::path_utils::normalize_virtual_path(
             &url_str,
             &self.scope.source_path,
@@ -81,8 +177,32 @@ impl<'a, 'i> Visitor<'i> for CssPreprocessVisitor<'a> {
     }
 }
 
+/// Appends `[attribute]` to the last compound selector of every selector, so
+/// `nav > a:hover` becomes `nav > a:hover[attribute]`.
+struct ScopedSelectorVisitor<'a> {
+    attribute: &'a str,
+}
+
+impl<'a, 'i> Visitor<'i> for ScopedSelectorVisitor<'a> {
+    type Error = Infallible;
+
+    fn visit_types(&self) -> VisitTypes {
+        visit_types!(SELECTORS)
+    }
+
+    fn visit_selector(&mut self, selector: &mut Selector<'i>) -> Result<(), Self::Error> {
+        selector.append(Component::AttributeInNoNamespaceExists {
+            local_name: self.attribute.to_string().into(),
+            local_name_lower: self.attribute.to_string().into(),
+        });
+        Ok(())
+    }
+}
+
 struct CssPostprocessVisitor<'a> {
-    environment: &'a PostprocessEnvironment,
+    context: &'a VirtualPathContext<'a>,
+    location: Option<&'a SourceLocation>,
+    aggregator: &'a mut Aggregator,
 }
 
 impl<'a, 'i> Visitor<'i> for CssPostprocessVisitor<'a> {
@@ -97,12 +217,92 @@ impl<'a, 'i> Visitor<'i> for CssPostprocessVisitor<'a> {
         let href = url.url.to_string();
         let resolved = crate::dependency_tracking::resolve_virtual_paths::to_resolved_path(
             &href,
-            &self.environment.origin_file_path,
-            &self.environment.output_file_path,
-            &self.environment.resolver
+            self.context.origin_file_path,
+            self.context.output_file_path,
+            self.context.resolver,
+            self.location,
+            self.aggregator,
         );
         url.url = resolved.into();
         Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope() -> Scope {
+        Scope {
+            project_root: PathBuf::from("/site"),
+            source_path: PathBuf::from("/site/pages/index.html"),
+            parser_backend: Default::default(),
+            inline_threshold: None,
+            url_attributes: Vec::new(),
+            site_url: None,
+            parse_cache: Default::default(),
+        }
+    }
+
+    fn scoped(source_code: &str) -> String {
+        let mut aggregator = Aggregator::default();
+        let css = pre_process(source_code, None, &scope(), Some("data-ssio-x"), &mut aggregator);
+        assert!(aggregator.diagnostics.is_empty(), "{:?}", aggregator.diagnostics);
+        css.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn scoping_narrows_the_last_compound_selector() {
+        assert_eq!(scoped("nav > a:hover { color: red }"), "nav > a:hover[data-ssio-x] { color: red; }");
+        assert_eq!(scoped("p { margin: 0 }"), "p[data-ssio-x] { margin: 0; }");
+    }
+
+    #[test]
+    fn scoping_narrows_every_selector_in_a_list() {
+        assert_eq!(scoped("h1, .title em { font-weight: bold }"), "h1[data-ssio-x], .title em[data-ssio-x] { font-weight: bold; }");
+    }
+
+    #[test]
+    fn scoping_reaches_nested_rules() {
+        assert_eq!(scoped("@media (width >= 600px) { li { float: left } }"), "@media (width >= 600px) { li[data-ssio-x] { float: left; } }");
+    }
+
+    fn parse_error_location(html: &str, css: &str) -> SourceLocation {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("index.html");
+        std::fs::write(&file, html).unwrap();
+        let tag = html.find("<style").unwrap();
+        let line = html[..tag].matches('\n').count() + 1;
+        let column = tag - html[..tag].rfind('\n').map_or(0, |ix| ix + 1) + 1;
+        let location = SourceLocation::new(file.as_path().into(), crate::diagnostics::SourcePosition { line, column });
+        let mut aggregator = Aggregator::default();
+        pre_process(css, Some(&location), &scope(), None, &mut aggregator);
+        assert_eq!(aggregator.diagnostics.len(), 1, "{:?}", aggregator.diagnostics);
+        let diagnostic = aggregator.diagnostics.remove(0);
+        assert_eq!(diagnostic.severity, crate::diagnostics::Severity::Warning);
+        diagnostic.location.unwrap()
+    }
+
+    #[test]
+    fn parse_errors_are_located_from_the_start_of_the_stylesheet() {
+        let css = "a { color: red } @media ( {";
+        let html = format!("<p>Hi</p>\n  <style media=\"a > b\">{css}</style>\n");
+        let location = parse_error_location(&html, css);
+        // The unclosed `@media` block is reported at the end of the stylesheet.
+        let error_column = "  <style media=\"a > b\">".len() + css.len() + 1;
+        assert_eq!(location.position, crate::diagnostics::SourcePosition { line: 2, column: error_column });
+
+        let css = "\na { color: red }\n  @media ( {";
+        let html = format!("<p>Hi</p>\n<style>{css}</style>\n");
+        let location = parse_error_location(&html, css);
+        assert_eq!(location.position, crate::diagnostics::SourcePosition { line: 4, column: 13 });
+    }
+
+    #[test]
+    fn unscoped_styles_keep_their_selectors() {
+        let mut aggregator = Aggregator::default();
+        let css = pre_process("nav > a { color: red }", None, &scope(), None, &mut aggregator);
+        assert_eq!(css.split_whitespace().collect::<Vec<_>>().join(" "), "nav > a { color: red; }");
+    }
+}
@@ -1,10 +1,11 @@
 use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::collections::HashSet;
 
 
 pub static REQUIRES_REGULAR_DEPENDENCY_TRACKING: Lazy<HashSet<(&'static str, &'static str)>> = Lazy::new(|| {
     HashSet::from([
-        ("a", "href"),
         ("area", "href"),
         ("link", "href"),
         ("img", "src"),
@@ -31,24 +32,134 @@ pub static REQUIRES_SRC_SET_DEPENDENCY_TRACKING: Lazy<HashSet<(&'static str, &'s
     HashSet::from([
         ("img", "srcset"),
         ("source", "srcset"),
+        ("link", "imagesrcset"),
     ])
 });
 
 pub static REQUIRES_DYNAMIC_SITE_LINK_DEPENDENCY_TRACKING: Lazy<HashSet<(&'static str, &'static str)>> = Lazy::new(|| {
     HashSet::from([
         ("a", "href"),
+        ("blockquote", "cite"),
+        ("q", "cite"),
+        ("del", "cite"),
+        ("ins", "cite"),
+    ])
+});
+
+/// `<meta name>` values whose `content` is a URL.
+pub static URL_METADATA: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    HashSet::from([
+        "msapplication-tileimage",
+    ])
+});
+
+/// `<meta property|name>` values whose `content` is a URL crawlers need absolute:
+/// Open Graph and Twitter card media.
+pub static ABSOLUTE_URL_METADATA: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    HashSet::from([
+        "og:image",
+        "og:image:url",
+        "og:image:secure_url",
+        "og:video",
+        "og:video:url",
+        "og:video:secure_url",
+        "og:audio",
+        "og:audio:url",
+        "og:audio:secure_url",
+        "twitter:image",
+        "twitter:image:src",
     ])
 });
 
-pub static TAG_MAY_REQUIRE_DEPENDENCY_TRACKING: Lazy<HashSet<&'static str>> = Lazy::new(|| { tags_only() });
+/// `<param name>` values (an `<object>`'s fallback parameters) whose `value` is a URL.
+pub static URL_PARAMS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    HashSet::from([
+        "movie",
+        "src",
+        "url",
+        "data",
+        "filename",
+    ])
+});
 
-fn tags_only() -> HashSet<&'static str> {
-    let xs = REQUIRES_REGULAR_DEPENDENCY_TRACKING
+/// How an attribute's value refers to local files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlKind {
+    /// A file published along with the page: an image, a script, a stylesheet…
+    #[default]
+    Asset,
+    /// Another page of the site.
+    Link,
+    /// A list of image candidates, like `srcset`.
+    Srcset,
+    /// Inline CSS declarations, like `style`; each `url()` is an asset.
+    Style,
+    /// An asset whose URL must be absolute, like `og:image`: it's written as
+    /// [`crate::compile::Compiler::site_url`] followed by the asset's output path, or
+    /// left as written when there is no site URL.
+    Absolute,
+}
+
+/// An extra URL attribute, declared in the manifest's `[[url_attributes]]` (typically
+/// for custom elements). It takes precedence over the built-in tables.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UrlAttribute {
+    pub tag: String,
+    pub attribute: String,
+    #[serde(default)]
+    pub kind: UrlKind,
+}
+
+/// How the `attribute` of a `tag` element refers to local files, if it does. `tag` and
+/// `attribute` are lowercase; `attributes` are the element's, for entries that depend
+/// on another attribute (`<meta property="og:image" content>`, `<input type="image"
+/// src>`, `<param name="movie" value>`).
+pub fn url_kind(
+    tag: &str,
+    attribute: &str,
+    attributes: &HashMap<String, String>,
+    custom: &[UrlAttribute],
+) -> Option<UrlKind> {
+    let declared = custom
         .iter()
-        .chain(REQUIRES_SRC_SET_DEPENDENCY_TRACKING.iter())
-        .map(|(x, _)| *x);
-    let result: HashSet<&'static str> = HashSet::from_iter(xs);
-    result
+        .find(|entry| entry.tag.eq_ignore_ascii_case(tag) && entry.attribute.eq_ignore_ascii_case(attribute));
+    if let Some(entry) = declared {
+        return Some(entry.kind)
+    }
+    if REQUIRES_DYNAMIC_SITE_LINK_DEPENDENCY_TRACKING.contains(&(tag, attribute)) {
+        return Some(UrlKind::Link)
+    }
+    if REQUIRES_REGULAR_DEPENDENCY_TRACKING.contains(&(tag, attribute)) {
+        return Some(UrlKind::Asset)
+    }
+    if REQUIRES_SRC_SET_DEPENDENCY_TRACKING.contains(&(tag, attribute)) {
+        return Some(UrlKind::Srcset)
+    }
+    let named = |names: &[&str], table: &HashSet<&'static str>| {
+        names
+            .iter()
+            .filter_map(|name| attribute_value(attributes, name))
+            .any(|value| table.contains(value.trim().to_ascii_lowercase().as_str()))
+    };
+    match (tag, attribute) {
+        (_, "style") => Some(UrlKind::Style),
+        ("meta", "content") if named(&["property", "name"], &URL_METADATA) => Some(UrlKind::Asset),
+        ("meta", "content") if named(&["property", "name"], &ABSOLUTE_URL_METADATA) => Some(UrlKind::Absolute),
+        ("input", "src") if attribute_value(attributes, "type").is_some_and(|kind| kind.trim().eq_ignore_ascii_case("image")) => {
+            Some(UrlKind::Asset)
+        }
+        ("param", "value") if named(&["name"], &URL_PARAMS) => Some(UrlKind::Asset),
+        _ => None,
+    }
+}
+
+fn attribute_value<'a>(attributes: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -137,4 +248,85 @@ impl SrcsetCandidate {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(tag: &str, attribute: &str, attributes: &[(&str, &str)]) -> Option<UrlKind> {
+        let attributes = attributes
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        url_kind(tag, attribute, &attributes, &[])
+    }
+
+    #[test]
+    fn metadata_urls_depend_on_the_name() {
+        assert_eq!(kind("meta", "content", &[("name", "msapplication-TileImage")]), Some(UrlKind::Asset));
+        assert_eq!(kind("meta", "content", &[("name", "description")]), None);
+    }
+
+    #[test]
+    fn social_metadata_urls_are_absolute() {
+        for name in ["og:image", "og:video:url", "OG:Audio:Secure_URL", "twitter:image"] {
+            assert_eq!(kind("meta", "content", &[("property", name)]), Some(UrlKind::Absolute), "{name}");
+            assert_eq!(kind("meta", "content", &[("name", name)]), Some(UrlKind::Absolute), "{name}");
+        }
+        assert_eq!(kind("meta", "content", &[("property", "og:title")]), None);
+    }
+
+    /// Builds a post whose template has an `og:image`; returns the `og:image` the post
+    /// was written with.
+    fn published_og_image(site_url: Option<&str>) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("media")).unwrap();
+        std::fs::create_dir_all(root.join("blog")).unwrap();
+        std::fs::write(root.join("media/og.png"), "png").unwrap();
+        std::fs::write(root.join("base.html"), "<html><head><meta property=\"og:image\" content=\"media/og.png\"></head><body><content></content></body></html>").unwrap();
+        std::fs::write(root.join("blog/post.html"), "<p>post</p>").unwrap();
+        let mut compiler = crate::compile::Compiler::new(root, root.join("output"));
+        compiler.template_path = Some(root.join("base.html"));
+        compiler.input_paths = vec![crate::compile::InputRule::new(root.join("blog/post.html"))];
+        compiler.pretty_print = false;
+        compiler.site_url = site_url.map(String::from);
+        let report = compiler.run().unwrap();
+        assert!(report.diagnostics.is_empty(), "{:?}", report.diagnostics);
+        assert_eq!(std::fs::read_to_string(root.join("output/media/og.png")).unwrap(), "png");
+        let post = std::fs::read_to_string(root.join("output/blog/post.html")).unwrap();
+        let (_, content) = post.split_once("content=\"").unwrap();
+        content[..content.find('"').unwrap()].to_string()
+    }
+
+    #[test]
+    fn social_metadata_publishes_its_asset() {
+        assert_eq!(published_og_image(None), "media/og.png");
+        assert_eq!(published_og_image(Some("https://example.com/")), "https://example.com/media/og.png");
+        assert_eq!(published_og_image(Some("https://example.com/docs")), "https://example.com/docs/media/og.png");
+    }
+
+    #[test]
+    fn only_image_inputs_have_a_source() {
+        assert_eq!(kind("input", "src", &[("type", "Image")]), Some(UrlKind::Asset));
+        assert_eq!(kind("input", "src", &[("type", "text")]), None);
+        assert_eq!(kind("input", "src", &[]), None);
+        assert_eq!(kind("input", "formaction", &[("type", "submit")]), Some(UrlKind::Asset));
+    }
 
+    #[test]
+    fn citations_are_links() {
+        for tag in ["a", "blockquote", "q", "del", "ins"] {
+            let attribute = if tag == "a" { "href" } else { "cite" };
+            assert_eq!(kind(tag, attribute, &[]), Some(UrlKind::Link), "{tag}");
+        }
+    }
+
+    #[test]
+    fn params_and_declared_attributes() {
+        assert_eq!(kind("param", "value", &[("name", "Movie")]), Some(UrlKind::Asset));
+        assert_eq!(kind("param", "value", &[("name", "autoplay")]), None);
+        let custom = [UrlAttribute { tag: String::from("my-hero"), attribute: String::from("image"), kind: UrlKind::Srcset }];
+        assert_eq!(url_kind("my-hero", "image", &HashMap::new(), &custom), Some(UrlKind::Srcset));
+        assert_eq!(kind("my-hero", "image", &[]), None);
+    }
+}
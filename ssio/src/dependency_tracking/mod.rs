@@ -1,4 +1,4 @@
-mod data;
+pub(crate) mod data;
 
 pub mod resolve_virtual_paths;
 pub mod virtualize_local_paths;
@@ -3,14 +3,13 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::html::Html;
-use crate::html::Element;
 use crate::compile::InputRule;
+use crate::diagnostics::{Diagnostic, SourceLocation};
+use crate::html_pass::system::Aggregator;
 
-use super::data::REQUIRES_REGULAR_DEPENDENCY_TRACKING;
-use super::data::TAG_MAY_REQUIRE_DEPENDENCY_TRACKING;
-use super::data::REQUIRES_SRC_SET_DEPENDENCY_TRACKING;
 use super::data::SrcsetCandidate;
+use super::data::UrlAttribute;
+use super::data::UrlKind;
 
 #[derive(Debug, Clone)]
 pub struct VirtualPathContext<'a> {
@@ -20,40 +19,85 @@ pub struct VirtualPathContext<'a> {
     pub output_file_path: &'a Path,
     /// The virtual link resolver
     pub resolver: &'a PathResolver,
+    /// Extra URL attributes (see [`super::data::url_kind`]).
+    pub url_attributes: &'a [UrlAttribute],
+    /// See [`crate::compile::Compiler::site_url`].
+    pub site_url: Option<&'a str>,
 }
 
 // === Rewriting Paths in Html ===
 
-pub fn resolve_virtual_paths(tag: &str, attributes: &mut HashMap<String, String>, context: &VirtualPathContext) {
+pub fn resolve_virtual_paths(
+    tag: &str,
+    attributes: &mut HashMap<String, String>,
+    location: Option<&SourceLocation>,
+    context: &VirtualPathContext,
+    aggregator: &mut Aggregator,
+) {
     let tag = tag.to_lowercase();
-    for (key, value) in attributes.iter_mut() {
-        let key = key.to_lowercase();
-        if REQUIRES_REGULAR_DEPENDENCY_TRACKING.contains(&(&tag, &key)) {
-            rewrite_path(
-                value,
-                &context.origin_file_path,
-                &context.output_file_path,
-                &context.resolver,
-            );
-        }
-        else if REQUIRES_SRC_SET_DEPENDENCY_TRACKING.contains(&(&tag, &key)) {
-            let source_sets = SrcsetCandidate::parse_srcset(value)
-                .into_iter()
-                .map(|SrcsetCandidate { mut url, descriptor }| {
-                    rewrite_path(
-                        &mut url,
-                        &context.origin_file_path,
-                        &context.output_file_path,
-                        &context.resolver,
-                    );
-                    SrcsetCandidate {
-                        url,
-                        descriptor: descriptor,
-                    }
-                })
-                .collect::<Vec<_>>();
-            let rewritten_source_sets = SrcsetCandidate::format_srcset(&source_sets);
-            *value = rewritten_source_sets;
+    let tracked = attributes
+        .keys()
+        .filter_map(|key| {
+            let kind = super::data::url_kind(&tag, &key.to_lowercase(), attributes, context.url_attributes)?;
+            Some((key.clone(), kind))
+        })
+        .collect::<Vec<_>>();
+    for (key, kind) in tracked {
+        let value = attributes.get_mut(&key).unwrap();
+        match kind {
+            UrlKind::Asset | UrlKind::Link => {
+                rewrite_path(
+                    value,
+                    &context.origin_file_path,
+                    &context.output_file_path,
+                    &context.resolver,
+                    location,
+                    aggregator,
+                );
+            }
+            // Only virtualized when there is a site URL; otherwise left as written.
+            UrlKind::Absolute => {
+                let Some(site_url) = context.site_url.filter(|_| value.starts_with("@/")) else {
+                    continue
+                };
+                // Relative to a page at the root of the output directory, which is
+                // where the site URL points.
+                rewrite_path(
+                    value,
+                    context.origin_file_path,
+                    &context.resolver.output_dir.join("index.html"),
+                    context.resolver,
+                    location,
+                    aggregator,
+                );
+                if !value.starts_with("@/") {
+                    *value = format!("{}/{}", site_url.trim_end_matches('/'), value.replace('\\', "/"));
+                }
+            }
+            UrlKind::Srcset => {
+                let source_sets = SrcsetCandidate::parse_srcset(value)
+                    .into_iter()
+                    .map(|SrcsetCandidate { mut url, descriptor }| {
+                        rewrite_path(
+                            &mut url,
+                            &context.origin_file_path,
+                            &context.output_file_path,
+                            &context.resolver,
+                            location,
+                            aggregator,
+                        );
+                        SrcsetCandidate {
+                            url,
+                            descriptor: descriptor,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let rewritten_source_sets = SrcsetCandidate::format_srcset(&source_sets);
+                *value = rewritten_source_sets;
+            }
+            UrlKind::Style => {
+                *value = crate::css_process::post_process_style_attribute(value, location, context, aggregator);
+            }
         }
     }
 }
@@ -63,12 +107,14 @@ pub fn to_resolved_path(
     origin_file: &Path,
     output_file: &Path,
     resolver: &PathResolver,
+    location: Option<&SourceLocation>,
+    aggregator: &mut Aggregator,
 ) -> String {
     let mut result = href.to_string();
     if !result.starts_with("@/") {
         return result
     }
-    rewrite_path(&mut result, origin_file, output_file, resolver);
+    rewrite_path(&mut result, origin_file, output_file, resolver, location, aggregator);
     result
 }
 
@@ -77,6 +123,8 @@ fn rewrite_path(
     origin_file: &Path,
     output_file: &Path,
     resolver: &PathResolver,
+    location: Option<&SourceLocation>,
+    aggregator: &mut Aggregator,
 ) {
     if crate::path_utils::is_external_url(href) {
         return;
@@ -96,15 +144,16 @@ fn rewrite_path(
         if let Some(relative) = pathdiff::diff_paths(&dest_output_path, output_file.parent().unwrap()) {
             *href = relative.to_string_lossy().to_string();
         } else {
-            eprintln!("⚠️  Failed diff_paths from {dest_output_path:?} to {:?}", output_file);
+            let diagnostic = Diagnostic::warning(format!("failed diff_paths from {dest_output_path:?} to {output_file:?}"))
+                .with_file(origin_file)
+                .with_location(location);
+            aggregator.diagnostics.push(diagnostic);
         }
     } else {
-        eprintln!(
-            "⚠️  Could not resolve output path for target {:?} (normalized: {:?}) in {:?}",
-            href,
-            resolved_target,
-            origin_file,
-        );
+        let diagnostic = Diagnostic::warning(format!("could not resolve output path for {href:?} (normalized: {resolved_target:?})"))
+            .with_file(origin_file)
+            .with_location(location);
+        aggregator.diagnostics.push(diagnostic);
     }
 }
 
@@ -1,18 +1,12 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-use crate::html::Html;
-use crate::html::Element;
-use crate::html_pass::system::State;
 use crate::html_pass::system::Aggregator;
 use crate::html_pass::system::Dependency;
 use crate::html_pass::system::Scope;
 
-use super::data::REQUIRES_REGULAR_DEPENDENCY_TRACKING;
-use super::data::REQUIRES_SRC_SET_DEPENDENCY_TRACKING;
-use super::data::TAG_MAY_REQUIRE_DEPENDENCY_TRACKING;
-use super::data::REQUIRES_DYNAMIC_SITE_LINK_DEPENDENCY_TRACKING;
 use super::data::SrcsetCandidate;
+use super::data::UrlKind;
 
 pub fn virtualize_and_register_local_paths(
     tag: &str,
@@ -21,59 +15,70 @@ pub fn virtualize_and_register_local_paths(
     aggregator: &mut Aggregator
 ) {
     let tag = tag.to_lowercase();
-    if !TAG_MAY_REQUIRE_DEPENDENCY_TRACKING.contains(tag.as_str()) {
-        return 
-    }
+    let tracked = attributes
+        .keys()
+        .filter_map(|key| {
+            let kind = super::data::url_kind(&tag, &key.to_lowercase(), attributes, &scope.url_attributes)?;
+            Some((key.clone(), kind))
+        })
+        .collect::<Vec<_>>();
     // - -
-    for (key, value) in attributes.iter_mut() {
-        let key = key.to_lowercase();
-        if let Some(rewritten) = try_to_virtual_path_value(&tag, &key, &value, scope) {
-            let source = path_clean::clean(scope.source_path.clone());
-            let target = path_clean::clean(PathBuf::from(&value));
-            if REQUIRES_DYNAMIC_SITE_LINK_DEPENDENCY_TRACKING.contains(&(&tag, &key)) {
-                aggregator.source_dependencies.insert(Dependency { origin: source, target, is_internal: None });
-            } else {
-                let is_internal = tag == "include";
-                aggregator.static_dependencies.insert(Dependency { origin: source, target, is_internal: Some(is_internal) });
+    for (key, kind) in tracked {
+        let value = attributes.get_mut(&key).unwrap();
+        let source = path_clean::clean(scope.source_path.clone());
+        match kind {
+            UrlKind::Asset | UrlKind::Link => {
+                if crate::path_utils::is_external_url(value) {
+                    continue;
+                }
+                let target = path_clean::clean(PathBuf::from(&value));
+                if kind == UrlKind::Link {
+                    aggregator.source_dependencies.insert(Dependency { origin: source, target, is_internal: None });
+                } else {
+                    let is_internal = tag == "include";
+                    aggregator.static_dependencies.insert(Dependency { origin: source, target, is_internal: Some(is_internal) });
+                }
+                *value = virtual_path(value, scope);
+            }
+            UrlKind::Absolute => {
+                if crate::path_utils::is_external_url(value) {
+                    continue;
+                }
+                let target = path_clean::clean(PathBuf::from(&value));
+                aggregator.static_dependencies.insert(Dependency { origin: source, target, is_internal: Some(false) });
+                // Written from the site URL once the output path is known.
+                if scope.site_url.is_some() {
+                    *value = virtual_path(value, scope);
+                }
+            }
+            UrlKind::Srcset => {
+                let source_sets = SrcsetCandidate::parse_srcset(value)
+                    .into_iter()
+                    .map(|SrcsetCandidate { url, descriptor }| {
+                        if crate::path_utils::is_external_url(&url) {
+                            return SrcsetCandidate { url, descriptor }
+                        }
+                        let target = path_clean::clean(PathBuf::from(&url));
+                        aggregator.static_dependencies.insert(Dependency { origin: source.clone(), target, is_internal: Some(false) });
+                        SrcsetCandidate {
+                            url: virtual_path(&url, scope),
+                            descriptor,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                *value = SrcsetCandidate::format_srcset(&source_sets);
+            }
+            UrlKind::Style => {
+                *value = crate::css_process::pre_process_style_attribute(value, scope, aggregator);
             }
-            *value = rewritten;
         }
     }
 }
 
-fn try_to_virtual_path_value(tag: &str, key: &str, value: &str, scope: &Scope) -> Option<String> {
-    // REGAULR
-    if REQUIRES_REGULAR_DEPENDENCY_TRACKING.contains(&(&tag, &key)) {
-        let target = PathBuf::from(value);
-        let virtual_src = crate::path_utils::normalize_virtual_path(
-            value,
-            &scope.source_path,
-            &scope.project_root,
-        );
-        return Some(virtual_src)
-    }
-    // SPECIAL
-    else if REQUIRES_SRC_SET_DEPENDENCY_TRACKING.contains(&(&tag, &key)) {
-        let source_sets = SrcsetCandidate::parse_srcset(value)
-            .into_iter()
-            .map(|SrcsetCandidate { url, descriptor }| {
-                let virtual_src = crate::path_utils::normalize_virtual_path(
-                    &url,
-                    &scope.source_path,
-                    &scope.project_root,
-                );
-                SrcsetCandidate {
-                    url: virtual_src,
-                    descriptor,
-                }
-            })
-            .collect::<Vec<_>>();
-        let rewritten_source_sets = SrcsetCandidate::format_srcset(&source_sets);
-        return Some(rewritten_source_sets)
-    }
-    None
+fn virtual_path(value: &str, scope: &Scope) -> String {
+    crate::path_utils::normalize_virtual_path(
+        value,
+        &scope.source_path,
+        &scope.project_root,
+    )
 }
-
-
-
-
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+// ————————————————————————————————————————————————————————————————————————————
+// DATA MODEL
+// ————————————————————————————————————————————————————————————————————————————
+
+/// A 1-based line/column position within a source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where a parsed node came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub file: Arc<Path>,
+    pub position: SourcePosition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Severity { Warning, Error }
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The file the diagnostic refers to, used when no precise location is known.
+    pub file: Option<Arc<Path>>,
+    pub location: Option<SourceLocation>,
+}
+
+// ————————————————————————————————————————————————————————————————————————————
+// CONSTRUCTION
+// ————————————————————————————————————————————————————————————————————————————
+
+impl Diagnostic {
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+    fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic { severity, message: message.into(), file: None, location: None }
+    }
+    pub fn with_file(mut self, file: impl AsRef<Path>) -> Self {
+        self.file = Some(Arc::from(file.as_ref()));
+        self
+    }
+    /// Attaches a precise location; falls back to keeping the file only when `None`.
+    pub fn with_location(mut self, location: Option<&SourceLocation>) -> Self {
+        if let Some(location) = location {
+            self.file = Some(location.file.clone());
+            self.location = Some(location.clone());
+        }
+        self
+    }
+    /// Prints the diagnostic to stderr.
+    pub fn emit(&self) {
+        eprintln!("{self}");
+    }
+}
+
+impl SourceLocation {
+    pub fn new(file: Arc<Path>, position: SourcePosition) -> Self {
+        SourceLocation { file, position }
+    }
+    /// Offsets this location by a position relative to the start of an embedded
+    /// source (e.g. a line within a `<style>` block); `line` counts from 0 and
+    /// `column` from 1.
+    pub fn offset_by(&self, line: usize, column: usize) -> Self {
+        let position = if line == 0 {
+            SourcePosition { line: self.position.line, column: self.position.column + column.saturating_sub(1) }
+        } else {
+            SourcePosition { line: self.position.line + line, column: column.max(1) }
+        };
+        SourceLocation { file: self.file.clone(), position }
+    }
+}
+
+// ————————————————————————————————————————————————————————————————————————————
+// RENDERING
+// ————————————————————————————————————————————————————————————————————————————
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.position.line, self.position.column)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Warning => "⚠️ warning",
+            Severity::Error => "❌ error",
+        };
+        writeln!(f, "{label}: {}", self.message)?;
+        match (&self.location, &self.file) {
+            (Some(location), _) => {
+                writeln!(f, "  --> {location}")?;
+                if let Some(snippet) = render_snippet(location) {
+                    write!(f, "{snippet}")?;
+                }
+            }
+            (None, Some(file)) => {
+                writeln!(f, "  --> {}", file.display())?;
+            }
+            (None, None) => (),
+        }
+        Ok(())
+    }
+}
+
+/// Renders the offending source line with a caret under the column, compiler style.
+fn render_snippet(location: &SourceLocation) -> Option<String> {
+    let source = std::fs::read_to_string(&location.file).ok()?;
+    let line = source.lines().nth(location.position.line.checked_sub(1)?)?;
+    let gutter = location.position.line.to_string();
+    let padding = " ".repeat(gutter.len());
+    let caret_offset = line
+        .chars()
+        .take(location.position.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    Some(format!(
+        "{padding} |\n{gutter} | {line}\n{padding} | {caret_offset}^\n"
+    ))
+}
@@ -0,0 +1,232 @@
+//! The site dependency graph: which files include, link to and reference which.
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::compile::BuildPlan;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Page,
+    Template,
+    Include,
+    Asset,
+    /// Referenced but not found on disk.
+    Missing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// A page is baked into a layout, or a layout into its parent.
+    Template,
+    /// An `<include>`.
+    Include,
+    /// A referenced image, stylesheet, script, …
+    Asset,
+    /// An `<a href>` to another page or file.
+    Link,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphNode {
+    /// The file's path relative to the project root.
+    pub id: String,
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SiteGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl SiteGraph {
+    /// Builds the graph from a (usually dry-run) build plan.
+    pub fn from_plan(plan: &BuildPlan) -> Self {
+        let mut nodes = BTreeMap::<PathBuf, NodeKind>::new();
+        let mut edges = BTreeSet::<(PathBuf, PathBuf, EdgeKind)>::new();
+        for page in plan.pages.iter() {
+            nodes.insert(page.source.clone(), NodeKind::Page);
+        }
+        for page in plan.pages.iter() {
+            let mut from = &page.source;
+            for layout in page.layouts.iter() {
+                nodes.insert(layout.clone(), NodeKind::Template);
+                edges.insert((from.clone(), layout.clone(), EdgeKind::Template));
+                from = layout;
+            }
+        }
+        for dependency in plan.pages.iter().flat_map(|page| page.dependencies.iter()) {
+            let Some(target) = resolved_target(&dependency.origin, &dependency.target) else {
+                continue
+            };
+            let kind = match dependency.is_internal {
+                Some(true) => EdgeKind::Include,
+                Some(false) => EdgeKind::Asset,
+                None => EdgeKind::Link,
+            };
+            let target_kind = match kind {
+                _ if !target.exists() => NodeKind::Missing,
+                EdgeKind::Include => NodeKind::Include,
+                _ => NodeKind::Asset,
+            };
+            // Pages and the template keep their kind when also included or linked.
+            nodes.entry(target.clone()).or_insert(target_kind);
+            edges.insert((dependency.origin.clone(), target, kind));
+        }
+        let id = |path: &Path| node_id(&plan.project_root, path);
+        SiteGraph {
+            nodes: nodes
+                .iter()
+                .map(|(path, kind)| GraphNode { id: id(path), kind: *kind })
+                .collect(),
+            edges: edges
+                .iter()
+                .map(|(from, to, kind)| GraphEdge { from: id(from), to: id(to), kind: *kind })
+                .collect(),
+        }
+    }
+    /// Renders the graph in Graphviz's DOT language.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph site {\n    rankdir=LR;\n");
+        for node in self.nodes.iter() {
+            let attrs = match node.kind {
+                NodeKind::Page => "shape=box",
+                NodeKind::Template => "shape=box3d",
+                NodeKind::Include => "shape=note",
+                NodeKind::Asset => "shape=ellipse",
+                NodeKind::Missing => "shape=ellipse, style=dashed, color=red",
+            };
+            dot.push_str(&format!("    {:?} [{attrs}];\n", node.id));
+        }
+        for edge in self.edges.iter() {
+            let attrs = match edge.kind {
+                EdgeKind::Template => "style=bold",
+                EdgeKind::Include => "style=dashed",
+                EdgeKind::Asset => "style=dotted",
+                EdgeKind::Link => "style=solid",
+            };
+            let label = serde_json::to_value(edge.kind).unwrap();
+            dot.push_str(&format!("    {:?} -> {:?} [label={label}, {attrs}];\n", edge.from, edge.to));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// A file's node id: its path relative to the project root.
+pub fn node_id(project_root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(project_root).unwrap_or(path);
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+/// The file a dependency points at, without any query or fragment; `None` for
+/// same-page links like `#top`.
+fn resolved_target(origin: &Path, target: &Path) -> Option<PathBuf> {
+    let target = target.to_string_lossy();
+    let target = target.split(['#', '?']).next().unwrap_or_default();
+    if target.is_empty() {
+        return None
+    }
+    let origin_dir = origin.parent()?;
+    Some(path_clean::clean(origin_dir.join(target)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::PagePlan;
+    use crate::html_pass::system::Dependency;
+
+    #[test]
+    fn renders_dot() {
+        let graph = SiteGraph {
+            nodes: vec![
+                GraphNode { id: String::from("index.html"), kind: NodeKind::Page },
+                GraphNode { id: String::from("gone.png"), kind: NodeKind::Missing },
+            ],
+            edges: vec![
+                GraphEdge { from: String::from("index.html"), to: String::from("gone.png"), kind: EdgeKind::Asset },
+            ],
+        };
+        assert_eq!(graph.to_dot(), concat!(
+            "digraph site {\n",
+            "    rankdir=LR;\n",
+            "    \"index.html\" [shape=box];\n",
+            "    \"gone.png\" [shape=ellipse, style=dashed, color=red];\n",
+            "    \"index.html\" -> \"gone.png\" [label=\"asset\", style=dotted];\n",
+            "}\n",
+        ));
+    }
+
+    #[test]
+    fn builds_graph_from_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        for file in ["index.html", "about.html", "base.html", "nav.html", "logo.png"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let dependency = |target: &str, is_internal| Dependency {
+            origin: root.join("index.html"),
+            target: PathBuf::from(target),
+            is_internal,
+        };
+        let page = |name: &str, dependencies| PagePlan {
+            source: root.join(name),
+            output: root.join("output").join(name),
+            includes: Vec::default(),
+            layouts: vec![root.join("base.html")],
+            cached: false,
+            dependencies,
+        };
+        let plan = BuildPlan {
+            project_root: root.clone(),
+            pages: vec![
+                page("index.html", vec![
+                    dependency("nav.html", Some(true)),
+                    dependency("logo.png", Some(false)),
+                    dependency("about.html#team", None),
+                    dependency("missing.png", Some(false)),
+                    dependency("#top", None),
+                ]),
+                page("about.html", Vec::default()),
+            ],
+            ..Default::default()
+        };
+        let graph = SiteGraph::from_plan(&plan);
+        let nodes = graph.nodes
+            .iter()
+            .map(|node| (node.id.as_str(), node.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(nodes, [
+            ("about.html", NodeKind::Page),
+            ("base.html", NodeKind::Template),
+            ("index.html", NodeKind::Page),
+            ("logo.png", NodeKind::Asset),
+            ("missing.png", NodeKind::Missing),
+            ("nav.html", NodeKind::Include),
+        ]);
+        let edges = graph.edges
+            .iter()
+            .map(|edge| (edge.from.as_str(), edge.to.as_str(), edge.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(edges, [
+            ("about.html", "base.html", EdgeKind::Template),
+            ("index.html", "about.html", EdgeKind::Link),
+            ("index.html", "base.html", EdgeKind::Template),
+            ("index.html", "logo.png", EdgeKind::Asset),
+            ("index.html", "missing.png", EdgeKind::Asset),
+            ("index.html", "nav.html", EdgeKind::Include),
+        ]);
+    }
+}
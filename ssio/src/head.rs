@@ -0,0 +1,281 @@
+//! Hoisting page-level `<head>` elements out of `<content>`.
+//!
+//! A page (or a layout baked into a parent) contributes to the document head with
+//! top-level `<title>`, `<meta>`, `<link>` and `<script>` elements, or with anything
+//! inside a top-level `<head-append>`. They're taken out before the file is baked into
+//! its layout and merged into the final document's `<head>` afterwards; a `<title>`,
+//! or a `<meta>` with the same `name`, `property`, `http-equiv` or `charset`, replaces
+//! the layout's.
+//!
+//! Top-level `<style>` and `<script>` elements of included files are taken out as the
+//! file is included and collected, without duplicates, as [`ComponentAsset`]s; each
+//! page gets them once, styles at the end of its `<head>` and scripts at the end of its
+//! `<body>`.
+use crate::html::{Element, Html};
+use crate::html_pass::system::ComponentAsset;
+
+const HOISTED_TAGS: &[&str] = &["title", "meta", "link", "script"];
+
+const HEAD_APPEND_TAG: &str = "head-append";
+
+/// Removes the head elements at the top level of a page (nested fragments included).
+pub fn take_head_elements(html: &mut Html) -> Vec<Html> {
+    let Html::Fragment(nodes) = html else {
+        return Vec::default()
+    };
+    let mut hoisted = Vec::new();
+    let mut kept = Vec::with_capacity(nodes.len());
+    for mut node in std::mem::take(nodes) {
+        match &mut node {
+            Html::Element(element) if element.tag.eq_ignore_ascii_case(HEAD_APPEND_TAG) => {
+                let children = std::mem::take(&mut element.children);
+                hoisted.extend(children.into_iter().filter(|child| !is_whitespace(child)));
+            }
+            Html::Element(element) if is_hoisted(element) => hoisted.push(node),
+            Html::Fragment(_) => {
+                hoisted.extend(take_head_elements(&mut node));
+                kept.push(node);
+            }
+            _ => kept.push(node),
+        }
+    }
+    *nodes = kept;
+    hoisted
+}
+
+/// Merges hoisted elements into the document's `<head>`, in order, each replacing any
+/// element it duplicates. Without a `<head>` they're put back in front of the page.
+pub fn inject_head(mut html: Html, elements: Vec<Html>) -> Html {
+    if elements.is_empty() {
+        return html
+    }
+    let Some(head) = find_element(&mut html, "head") else {
+        let mut nodes = elements;
+        nodes.push(html);
+        return Html::Fragment(nodes)
+    };
+    for element in elements {
+        merge_into_head(head, element);
+    }
+    html
+}
+
+/// Removes the `<style>` and `<script>` elements at the top level of an included file
+/// (nested fragments included).
+pub fn take_component_assets(html: &mut Html) -> Vec<ComponentAsset> {
+    let Html::Fragment(nodes) = html else {
+        return Vec::default()
+    };
+    let mut assets = Vec::new();
+    let mut kept = Vec::with_capacity(nodes.len());
+    for mut node in std::mem::take(nodes) {
+        match node {
+            Html::Element(element) if is_component_asset(&element) => {
+                assets.push(ComponentAsset {
+                    code: Html::Fragment(element.children).to_text().unwrap_or_default(),
+                    tag: element.tag.to_ascii_lowercase(),
+                    attrs: element.attrs.into_iter().collect(),
+                });
+            }
+            Html::Fragment(_) => {
+                assets.extend(take_component_assets(&mut node));
+                kept.push(node);
+            }
+            _ => kept.push(node),
+        }
+    }
+    *nodes = kept;
+    assets
+}
+
+/// Adds a page's component assets: styles to the end of `<head>`, scripts to the end of
+/// `<body>`. Without either, they go before and after the page.
+pub fn inject_component_assets(mut html: Html, assets: Vec<ComponentAsset>) -> Html {
+    let (scripts, styles): (Vec<_>, Vec<_>) = assets
+        .into_iter()
+        .map(ComponentAsset::into_html)
+        .partition(|node| matches!(node, Html::Element(element) if element.tag == "script"));
+    let mut before = Vec::new();
+    let mut after = Vec::new();
+    match find_element(&mut html, "head") {
+        Some(head) => head.children.extend(styles),
+        None => before = styles,
+    }
+    match find_element(&mut html, "body") {
+        Some(body) => body.children.extend(scripts),
+        None => after = scripts,
+    }
+    if before.is_empty() && after.is_empty() {
+        return html
+    }
+    Html::Fragment(before.into_iter().chain([html]).chain(after).collect())
+}
+
+impl ComponentAsset {
+    fn into_html(self) -> Html {
+        Html::Element(Element {
+            tag: self.tag,
+            attrs: self.attrs.into_iter().collect(),
+            children: vec![Html::Text(self.code)],
+            location: None,
+        })
+    }
+}
+
+fn is_component_asset(element: &Element) -> bool {
+    element.tag.eq_ignore_ascii_case("style") || element.tag.eq_ignore_ascii_case("script")
+}
+
+fn is_hoisted(element: &Element) -> bool {
+    HOISTED_TAGS.iter().any(|tag| element.tag.eq_ignore_ascii_case(tag))
+}
+
+fn is_whitespace(node: &Html) -> bool {
+    matches!(node, Html::Text(text) if text.trim().is_empty())
+}
+
+fn find_element<'a>(html: &'a mut Html, tag: &str) -> Option<&'a mut Element> {
+    match html {
+        Html::Element(element) => {
+            if element.tag.eq_ignore_ascii_case(tag) {
+                return Some(element)
+            }
+            element.children.iter_mut().find_map(|child| find_element(child, tag))
+        }
+        Html::Fragment(nodes) => nodes.iter_mut().find_map(|node| find_element(node, tag)),
+        Html::Text(_) => None,
+    }
+}
+
+fn merge_into_head(head: &mut Element, node: Html) {
+    let key = match &node {
+        Html::Element(element) => duplicate_key(element),
+        _ => None,
+    };
+    let existing = key.and_then(|key| {
+        head.children.iter().position(|child| {
+            matches!(child, Html::Element(child) if duplicate_key(child).as_ref() == Some(&key))
+        })
+    });
+    match existing {
+        Some(index) => head.children[index] = node,
+        None => head.children.push(node),
+    }
+}
+
+/// What makes two head elements duplicates of each other.
+fn duplicate_key(element: &Element) -> Option<String> {
+    let tag = element.tag.to_ascii_lowercase();
+    match tag.as_str() {
+        "title" => Some(tag),
+        "meta" if element.attrs.contains_key("charset") => Some(String::from("charset")),
+        "meta" => {
+            ["name", "property", "http-equiv"]
+                .iter()
+                .find_map(|attr| {
+                    let value = element.attrs.get(*attr)?;
+                    Some(format!("{attr}={}", value.to_ascii_lowercase()))
+                })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::ParserMode;
+
+    fn fragment(source: &str) -> Html {
+        Html::parse(source, ParserMode::fragment("div"), Default::default())
+    }
+
+    fn document(source: &str) -> Html {
+        Html::parse(source, ParserMode::Document, Default::default())
+    }
+
+    /// A compact rendering of the tree: `tag[name=value](children)`, text quoted.
+    fn shape(html: &Html) -> String {
+        match html {
+            Html::Text(text) => format!("{text:?}"),
+            Html::Fragment(nodes) => nodes.iter().map(shape).collect::<Vec<_>>().join(" "),
+            Html::Element(element) => {
+                let mut attrs = element.attrs.iter().collect::<Vec<_>>();
+                attrs.sort();
+                let attrs = attrs
+                    .iter()
+                    .map(|(key, value)| format!("[{key}={value}]"))
+                    .collect::<String>();
+                let children = element.children.iter().map(shape).collect::<Vec<_>>().join(" ");
+                format!("{}{attrs}({children})", element.tag)
+            }
+        }
+    }
+
+    #[test]
+    fn takes_top_level_head_elements() {
+        let mut page = fragment(r#"<title>Page</title><p>body</p><head-append> <link rel="icon" href="a.png"> </head-append><div><meta name="x"></div>"#);
+        let hoisted = take_head_elements(&mut page);
+        assert_eq!(shape(&Html::Fragment(hoisted)), r#"title("Page") link[href=a.png][rel=icon]()"#);
+        assert_eq!(shape(&page), r#"p("body") div(meta[name=x]())"#);
+    }
+
+    #[test]
+    fn injected_elements_replace_their_duplicates() {
+        let layout = document(concat!(
+            r#"<html><head><meta charset="utf-8"><title>Site</title>"#,
+            r#"<meta name="description" content="site"><meta property="og:title" content="Site"></head>"#,
+            r#"<body></body></html>"#,
+        ));
+        let mut page = fragment(concat!(
+            r#"<meta charset="latin1"><title>Page</title><meta name="Description" content="page">"#,
+            r#"<link rel="stylesheet" href="page.css"><meta name="keywords" content="k">"#,
+        ));
+        let hoisted = take_head_elements(&mut page);
+        let html = inject_head(layout, hoisted);
+        assert_eq!(shape(&html), concat!(
+            r#"html(head(meta[charset=latin1]() title("Page") meta[content=page][name=Description]() "#,
+            r#"meta[content=Site][property=og:title]() link[href=page.css][rel=stylesheet]() "#,
+            r#"meta[content=k][name=keywords]()) body())"#,
+        ));
+    }
+
+    #[test]
+    fn elements_go_in_front_without_a_head() {
+        let html = inject_head(fragment("<p>x</p>"), vec![fragment("<title>Page</title>")]);
+        assert_eq!(shape(&html), r#"title("Page") p("x")"#);
+    }
+
+    #[test]
+    fn takes_top_level_component_assets() {
+        let mut include = fragment(r#"<style>.card {}</style><div class="card"><script>nested()</script></div><script type="module">run()</script>"#);
+        let assets = take_component_assets(&mut include);
+        let assets = assets
+            .iter()
+            .map(|asset| (asset.tag.as_str(), asset.code.as_str(), asset.attrs.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(assets, [("style", ".card {}", 0), ("script", "run()", 1)]);
+        assert_eq!(shape(&include), r#"div[class=card](script("nested()"))"#);
+    }
+
+    #[test]
+    fn pages_get_each_component_asset_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("base.html"), "<html><head><title>Site</title></head><body><content></content></body></html>").unwrap();
+        std::fs::write(root.join("card.html"), "<style>.card {}</style><div class=\"card\"></div><script>card()</script>").unwrap();
+        std::fs::write(root.join("index.html"), "<include src=\"card.html\"></include><include src=\"card.html\"></include>").unwrap();
+        let mut compiler = crate::compile::Compiler::new(root, root.join("output"));
+        compiler.template_path = Some(root.join("base.html"));
+        compiler.pretty_print = false;
+        compiler.input_paths = vec![crate::compile::InputRule::new(root.join("index.html"))];
+        let report = compiler.run().unwrap();
+        assert!(!report.has_errors(), "{:?}", report.diagnostics);
+        let output = std::fs::read_to_string(root.join("output/index.html")).unwrap();
+        let html = document(&output);
+        assert_eq!(shape(&html), concat!(
+            r#"html(head(title("Site") style(".card {\n}\n")) "#,
+            r#"body(div[class=card]() div[class=card]() script("card()")))"#,
+        ));
+    }
+}
@@ -1,5 +1,9 @@
 use std::collections::HashMap;
-use pretty_tree::{PrettyTreePrinter, ToPrettyTree};
+use std::path::Path;
+use std::sync::Arc;
+use pretty_tree::{ToPrettyTree};
+
+use crate::diagnostics::SourceLocation;
 
 // ————————————————————————————————————————————————————————————————————————————
 // DATA MODEL
@@ -17,28 +21,35 @@ pub struct Element {
     pub tag: String,
     pub attrs: HashMap<String, String>,
     pub children: Vec<Html>,
+    /// Where this element was written, for diagnostics.
+    pub location: Option<SourceLocation>,
 }
 
 impl Html {
-    pub fn parse(source: &str, mode: ParserMode) -> Html {
-        let result = match mode {
-            ParserMode::Document => Self::parse_document(source),
-            ParserMode::Fragment { context } => Self::parse_fragment(source, &context)
-        };
-        result
+    pub fn parse(source: &str, mode: ParserMode, backend: ParserBackend) -> Html {
+        Self::parse_with_origin(source, mode, backend, None)
+    }
+    /// Parses the contents of `file_path`, recording source locations on every element
+    /// (when the backend supports them).
+    pub fn parse_file(source: &str, mode: ParserMode, backend: ParserBackend, file_path: impl AsRef<Path>) -> Html {
+        let file: Arc<Path> = Arc::from(file_path.as_ref());
+        Self::parse_with_origin(source, mode, backend, Some(&file))
+    }
+    fn parse_with_origin(source: &str, mode: ParserMode, backend: ParserBackend, file: Option<&Arc<Path>>) -> Html {
+        match (backend, mode) {
+            (ParserBackend::Native, ParserMode::Document) => Self::parse_document(source).normalize(file),
+            (ParserBackend::Native, ParserMode::Fragment { context }) => Self::parse_fragment(source, &context).normalize(file),
+            (ParserBackend::RcDom, ParserMode::Document) => crate::html_parser::parse_html_document(source),
+            (ParserBackend::RcDom, ParserMode::Fragment { context }) => crate::html_parser::parse_html_fragment(source, &context),
+        }
     }
-    fn parse_fragment(source: &str, context: &str) -> Html {
-        crate::html_parser2::parse_html_fragment(source, context).normalize()
+    fn parse_fragment(source: &str, context: &str) -> crate::html_parser2::Html {
+        crate::html_parser2::parse_html_fragment(source, context)
         // let _ = context;
         // Self::parse_document(source)
     }
-    fn parse_document(source: &str) -> Html {
-        crate::html_parser2::parse_html_document(source).normalize()
-        // let result = crate::html_parser::parse_html_str(source);
-        // if result.payload.len() == 1 {
-        //     return result.payload.get(0).unwrap().clone()
-        // }
-        // Html::Fragment(result.payload)
+    fn parse_document(source: &str) -> crate::html_parser2::Html {
+        crate::html_parser2::parse_html_document(source)
     }
     pub fn to_text(&self) -> Result<String, ()> {
         match self {
@@ -50,9 +61,6 @@ impl Html {
 }
 
 impl Element {
-    pub fn has_tag(&self, tag: impl AsRef<str>) -> bool {
-        self.tag.to_lowercase() == tag.as_ref().to_lowercase()
-    }
     pub fn to_text(&self) -> Result<String, ()> {
         fragment_to_text(&self.children)
     }
@@ -81,11 +89,11 @@ fn fragment_to_text(nodes: &[Html]) -> Result<String, ()> {
 // CONVERSTION
 // ————————————————————————————————————————————————————————————————————————————
 impl crate::html_parser2::Html {
-    pub fn normalize(self) -> Html {
+    pub fn normalize(self, file: Option<&Arc<Path>>) -> Html {
         match self {
-            crate::html_parser2::Html::Element(element) => element.normalize(),
+            crate::html_parser2::Html::Element(element) => element.normalize(file),
             crate::html_parser2::Html::Fragment(nodes) => {
-                let nodes = nodes.into_iter().map(|x| x.normalize()).collect();
+                let nodes = nodes.into_iter().map(|x| x.normalize(file)).collect();
                 Html::Fragment(nodes)
             },
             crate::html_parser2::Html::Text(text) => Html::Text(text),
@@ -94,12 +102,15 @@ impl crate::html_parser2::Html {
 }
 
 impl crate::html_parser2::Element {
-    pub fn normalize(self) -> Html {
+    pub fn normalize(self, file: Option<&Arc<Path>>) -> Html {
         let children = self.children
             .into_iter()
-            .map(|x| x.normalize())
+            .map(|x| x.normalize(file))
             .collect();
-        Html::Element(Element { tag: self.tag, attrs: self.attrs, children: children })
+        let location = file
+            .zip(self.position)
+            .map(|(file, position)| SourceLocation::new(file.clone(), position));
+        Html::Element(Element { tag: self.tag, attrs: self.attrs, children: children, location })
     }
 }
 
@@ -158,30 +169,6 @@ impl ToPrettyTree for Element {
 // HTML API UTILITIES
 // ————————————————————————————————————————————————————————————————————————————
 
-impl Html {
-    pub fn as_element(&self) -> Option<&Element> {
-        match self {
-            Self::Element(element) => Some(element),
-            _ => None,
-        }
-    }
-    pub fn is_inline_node(&self) -> bool {
-        self.as_element().map(|x| x.is_inline_node()).unwrap_or(false)
-    }
-    pub fn is_header_tag(&self) -> bool {
-        self.as_element().map(|x| x.is_header_tag()).unwrap_or(false)
-    }
-}
-
-impl Element {
-    pub fn is_inline_node(&self) -> bool {
-        is_inline_tag(&self.tag)
-    }
-    pub fn is_header_tag(&self) -> bool {
-        is_header_tag(&self.tag)
-    }
-}
-
 /// Returns true if tag is a known inline element based on HTML5 content model.
 pub fn is_inline_tag(tag: &str) -> bool {
     match tag.to_ascii_lowercase().as_str() {
@@ -213,15 +200,6 @@ pub fn is_inline_tag(tag: &str) -> bool {
     }
 }
 
-pub fn is_header_tag(tag: &str) -> bool {
-    tag == "h1" ||
-    tag == "h2" ||
-    tag == "h3" ||
-    tag == "h4" ||
-    tag == "h5" ||
-    tag == "h6"
-}
-
 pub fn is_void_tag(tag: &str) -> bool {
     tag == "area" ||
     tag == "base" ||
@@ -242,7 +220,7 @@ pub fn is_void_tag(tag: &str) -> bool {
 // PARSER
 // ————————————————————————————————————————————————————————————————————————————
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ParserMode {
     Document, Fragment { context: String }
 }
@@ -252,3 +230,17 @@ impl ParserMode {
         Self::Fragment { context: context.as_ref().to_string() }
     }
 }
+
+/// Which tree builder turns html5ever's token stream into [`Html`]. Both produce the
+/// same tree; only `Native` records source locations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ParserBackend {
+    /// The arena based sink in `html_parser2`.
+    #[default]
+    Native,
+    /// The reference `markup5ever_rcdom` tree in `html_parser`.
+    #[value(name = "rcdom")]
+    #[serde(rename = "rcdom")]
+    RcDom,
+}
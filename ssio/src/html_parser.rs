@@ -1,8 +1,5 @@
 use std::collections::HashMap;
 use std::default::Default;
-use std::io;
-use std::iter::repeat;
-use std::string::String;
 
 use html5ever::{
     ns, parse_document, parse_fragment
@@ -15,29 +12,18 @@ use crate::html::{Element, Html};
 
 const REPORT_PARSER_ERRORS: bool = false;
 
-fn convert_impl(handle: &Handle) -> Vec<Html> {
+/// Converts an rcdom node into the same shape `html_parser2` produces, so either
+/// backend can feed the rest of the pipeline.
+fn convert_impl(handle: &Handle) -> Option<Html> {
     let node = handle;
     match node.data {
         NodeData::Text { ref contents } => {
-            let text = escape_default(&contents.borrow());
-            let is_empty = text
-                .trim()
-                .split_ascii_whitespace()
-                // .map(|x| x.clone())
-                .flat_map(|x| {x.lines()})
-                .filter(|x| {!x.is_empty()})
-                .filter(|x| {x != &"\\n"})
-                .collect::<Vec<_>>();
-            if is_empty.is_empty() {
-                vec![]
-            } else {
-                // println!("{:?}", text);
-                vec![Html::Text(text)]
-            }
+            Some(Html::Text(contents.borrow().to_string()))
         },
         NodeData::Element {
             ref name,
             ref attrs,
+            ref template_contents,
             ..
         } => {
             let tag = format!("{}", name.local);
@@ -45,149 +31,84 @@ fn convert_impl(handle: &Handle) -> Vec<Html> {
                 .borrow()
                 .iter()
                 .map(|x| {
-                    (format!("{}", x.name.local), format!("{}", x.value))
+                    let key = match &x.name.prefix {
+                        Some(prefix) if !prefix.is_empty() => format!("{}:{}", prefix, x.name.local),
+                        _ => format!("{}", x.name.local),
+                    };
+                    (key, format!("{}", x.value))
                 })
                 .collect::<HashMap<_, _>>();
-            let children = node
-                .children
-                .borrow()
-                .iter()
-                .map(|x| {
-                    convert_impl(x)
-                })
-                .filter(|x| !x.is_empty())
-                .flatten()
-                .collect::<Vec<_>>();
-            // vec![Html::new_element(
-            //     &tag,
-            //     attrs,
-            //     children,
-            // )]
-            vec![
-                Html::Element(Element{
-                    tag: tag,
-                    attrs: attrs,
-                    children: children,
-                })
-            ]
+            // Template contents live in a separate document fragment.
+            let children = template_contents
+                .as_ref()
+                .map(convert_children)
+                .unwrap_or_else(|| convert_children(node));
+            Some(Html::Element(Element{
+                tag: tag,
+                attrs: attrs,
+                children: children,
+                location: None,
+            }))
         },
-        _ => {
-            node
-                .children
-                .borrow()
-                .iter()
-                .map(|x| {
-                    convert_impl(x)
-                })
-                .filter(|x| !x.is_empty())
-                .flatten()
-                .collect::<Vec<_>>()
-        }
+        NodeData::Document => Some(Html::Fragment(convert_children(node))),
+        NodeData::Doctype { .. } |
+        NodeData::Comment { .. } |
+        NodeData::ProcessingInstruction { .. } => None,
     }
 }
 
-fn convert_root(handle: &Handle, document_mode: bool) -> Vec<Html> {
-    let result = convert_impl(handle);
-    if !document_mode {
-        match &result[..] {
-            [Html::Element(element)] if (element.tag == String::from("html")) => {
-                element.children.clone()
-            }
-            _ => {
-                unimplemented!()
+fn convert_children(handle: &Handle) -> Vec<Html> {
+    handle
+        .children
+        .borrow()
+        .iter()
+        .filter_map(convert_impl)
+        .collect::<Vec<_>>()
+}
+
+fn report_errors(dom: &RcDom) {
+    if !dom.errors.is_empty() {
+        if REPORT_PARSER_ERRORS {
+            eprintln!("\nParse errors:");
+            for err in dom.errors.iter() {
+                eprintln!("    {}", err);
             }
         }
-    } else {
-        result
     }
 }
 
-// FIXME: Copy of str::escape_default from std, which is currently unstable
-pub fn escape_default(s: &str) -> String {
-    // s.chars().flat_map(|c| c.escape_default()).collect()
-    String::from(s)
-}
-
-#[derive(Debug, Clone)]
-pub struct ParsedResult {
-    pub payload: Vec<Html>,
-    pub errors: Vec<String>,
-}
-
-
-fn tokenizer_config() -> html5ever::tokenizer::TokenizerOpts {
-    use markup5ever::{QualName, Namespace, LocalName, Prefix};
-    use markup5ever::interface::tree_builder::QuirksMode;
-    use html5ever::tokenizer::TokenizerOpts;
-    let mut ops: TokenizerOpts = Default::default();
-    // ops.exact_errors = false;
-    ops
-}
-
 fn parser_config() -> html5ever::driver::ParseOpts {
-    use markup5ever::{QualName, Namespace, LocalName, Prefix};
-    use markup5ever::interface::tree_builder::QuirksMode;
     let mut ops: html5ever::driver::ParseOpts = Default::default();
     ops.tree_builder = {
         let mut tree_build_ops = html5ever::tree_builder::TreeBuilderOpts::default();
         tree_build_ops.exact_errors = false;
-        tree_build_ops.scripting_enabled = true;
         tree_build_ops
     };
-    ops.tokenizer = tokenizer_config();
     ops
 }
 
+pub fn parse_html_document(input: &str) -> Html {
+    let dom = parse_document(RcDom::default(), parser_config())
+        .from_utf8()
+        .read_from(&mut input.as_bytes())
+        .unwrap();
+    report_errors(&dom);
+    Html::Fragment(convert_children(&dom.document))
+}
 
-pub fn parse_html_str(html_str: &str) -> ParsedResult {
-    use std::io::Cursor;
-    use markup5ever::{QualName, Namespace, LocalName, Prefix};
-    use markup5ever::interface::tree_builder::QuirksMode;
-    let mut source = Cursor::new(String::from(html_str));
-    let default_env = QualName::new(None, ns!(html), LocalName::from("div"));
-    let mut document_mode = {
-        html_str.contains("<html>")
-    };
-    let dom = {
-        if document_mode {
-            parse_document(
-                RcDom::default(),
-                parser_config(),
-            )
-            .from_utf8()
-            .read_from(&mut source)
-            .unwrap()
-        } else {
-            parse_fragment(
-                RcDom::default(),
-                parser_config(),
-                default_env,
-                Vec::new(),
-            )
-            .from_utf8()
-            .read_from(&mut source)
-            .unwrap()
-        }
-    };
-    
-    // TRAVERSE
-    let mut payload = convert_root(&dom.document, document_mode);
-
-    if !dom.errors.is_empty() {
-        if REPORT_PARSER_ERRORS {
-            eprintln!("\nParse errors:");
-            for err in dom.errors.iter() {
-                eprintln!("    {}", err);
-            }
-        }
+/// Parses a raw HTML fragment; `context` is the tag name of the element the fragment
+/// is parsed under (e.g. "div").
+pub fn parse_html_fragment(input: &str, context: &str) -> Html {
+    use markup5ever::{QualName, LocalName};
+    let context = QualName::new(None, ns!(html), LocalName::from(context));
+    let dom = parse_fragment(RcDom::default(), parser_config(), context, Vec::new())
+        .from_utf8()
+        .read_from(&mut input.as_bytes())
+        .unwrap();
+    report_errors(&dom);
+    let nodes = convert_children(&dom.document);
+    match &nodes[..] {
+        [Html::Element(element)] if element.tag == "html" => Html::Fragment(element.children.clone()),
+        _ => Html::Fragment(nodes),
     }
-    let errors = dom
-        .errors
-        .iter()
-        .map(|x| format!("{}", x))
-        .collect::<Vec<_>>();
-
-    ParsedResult{payload, errors}
 }
-
-
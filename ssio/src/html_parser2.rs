@@ -1,13 +1,14 @@
 use html5ever::tendril::TendrilSink;
 use html5ever::tree_builder::{TreeSink, NodeOrText, ElementFlags, QuirksMode};
-use html5ever::{parse_fragment, Attribute, QualName};
+use html5ever::{parse_fragment, Attribute, ExpandedName, QualName};
 use html5ever::{ns, namespace_url}; // required for ns! macro
 use tendril::Tendril;
 use tendril::fmt::UTF8;
 
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{HashMap, VecDeque};
+
+use crate::diagnostics::SourcePosition;
 
 // === Your AST ===
 
@@ -23,15 +24,24 @@ pub struct Element {
     pub tag: String,
     pub attrs: HashMap<String, String>,
     pub children: Vec<Html>,
+    /// Where the start tag appears in the source; `None` for elements implied by the parser.
+    pub position: Option<SourcePosition>,
 }
 
 // === Internal Tree Nodes ===
 
 #[derive(Debug)]
 enum NodeKind {
-    Element(Element),
+    Element {
+        name: QualName,
+        element: Element,
+        /// The "template contents" fragment; only set for `<template>` elements.
+        template_contents: Option<usize>,
+        mathml_annotation_xml_integration_point: bool,
+    },
     Text(String),
-    Comment(String),
+    Comment,
+    ProcessingInstruction,
     Fragment,
 }
 
@@ -42,30 +52,16 @@ struct Node {
     parent: Option<usize>,
 }
 
-// === Interning Support ===
-
-use html5ever::{LocalName, Namespace};
-
-static LOCAL_NAME_CACHE: Lazy<Mutex<HashMap<String, &'static LocalName>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-
-fn get_local(name: &str) -> &'static LocalName {
-    let mut cache = LOCAL_NAME_CACHE.lock().unwrap();
-    if let Some(existing) = cache.get(name) {
-        *existing
-    } else {
-        let interned: &'static LocalName = Box::leak(Box::new(LocalName::from(name)));
-        cache.insert(name.to_string(), interned);
-        interned
+impl Node {
+    fn new(kind: NodeKind) -> Self {
+        Node { kind, children: vec![], parent: None }
     }
 }
 
-static NAMESPACE_HTML: Lazy<&'static Namespace> = Lazy::new(|| {
-    Box::leak(Box::new(ns!(html)))
-});
-
-static NAMESPACE_EMPTY: Lazy<&'static Namespace> = Lazy::new(|| {
-    Box::leak(Box::new(ns!()))
+/// The tree builder only asks for the name of element nodes; this is returned
+/// for anything else rather than panicking.
+static NON_ELEMENT_NAME: Lazy<QualName> = Lazy::new(|| {
+    QualName::new(None, ns!(), html5ever::LocalName::from(""))
 });
 
 // === Sink ===
@@ -73,34 +69,88 @@ static NAMESPACE_EMPTY: Lazy<&'static Namespace> = Lazy::new(|| {
 struct FragmentSink {
     nodes: Vec<Node>,
     root_id: usize,
+    /// The line of the token currently being processed, as reported by the tree builder.
+    current_line: usize,
+    start_tags: StartTagIndex,
+    /// Set while parsing a fragment until the context element is created; it isn't in
+    /// the source, so it must not claim a start tag.
+    pending_context: bool,
 }
 
 impl FragmentSink {
-    fn new() -> Self {
-        let root = Node {
-            kind: NodeKind::Fragment,
-            children: vec![],
-            parent: None,
-        };
+    fn new(source: &str) -> Self {
         Self {
-            nodes: vec![root],
+            nodes: vec![Node::new(NodeKind::Fragment)],
             root_id: 0,
+            current_line: 1,
+            start_tags: StartTagIndex::new(source),
+            pending_context: false,
         }
     }
 
+    fn push_node(&mut self, kind: NodeKind) -> usize {
+        self.nodes.push(Node::new(kind));
+        self.nodes.len() - 1
+    }
+
     fn to_html(&self, node_id: usize) -> Html {
         let node = &self.nodes[node_id];
         match &node.kind {
             NodeKind::Fragment => {
-                Html::Fragment(node.children.iter().map(|&id| self.to_html(id)).collect())
+                Html::Fragment(self.children_to_html(&node.children))
             }
             NodeKind::Text(s) => Html::Text(s.clone()),
-            NodeKind::Element(e) => {
-                let mut el = e.clone();
-                el.children = node.children.iter().map(|&id| self.to_html(id)).collect();
+            NodeKind::Element { element, template_contents, .. } => {
+                // Template contents live in a separate fragment; serialize them as the
+                // element's children so `<template>` round-trips.
+                let children = template_contents
+                    .map(|id| &self.nodes[id].children)
+                    .unwrap_or(&node.children);
+                let mut el = element.clone();
+                el.children = self.children_to_html(children);
                 Html::Element(el)
             }
-            NodeKind::Comment(_) => Html::Fragment(vec![]), // ← skip it safely
+            NodeKind::Comment => Html::Fragment(vec![]), // ← skip it safely
+            NodeKind::ProcessingInstruction => Html::Fragment(vec![]),
+        }
+    }
+
+    fn children_to_html(&self, children: &[usize]) -> Vec<Html> {
+        children.iter().map(|&id| self.to_html(id)).collect()
+    }
+
+    /// Detaches `id` from its current parent, if any.
+    fn detach(&mut self, id: usize) {
+        if let Some(parent) = self.nodes[id].parent.take() {
+            self.nodes[parent].children.retain(|&child| child != id);
+        }
+    }
+
+    /// Inserts `child` into `parent` at `index`, merging adjacent text as required by
+    /// the tree builder.
+    fn insert_at(&mut self, parent: usize, index: usize, child: NodeOrText<usize>) {
+        match child {
+            NodeOrText::AppendNode(id) => {
+                self.detach(id);
+                // The index may have shifted if `id` was an earlier sibling under the same parent.
+                let index = index.min(self.nodes[parent].children.len());
+                self.nodes[id].parent = Some(parent);
+                self.nodes[parent].children.insert(index, id);
+            }
+            NodeOrText::AppendText(text) => {
+                let previous = index
+                    .checked_sub(1)
+                    .map(|ix| self.nodes[parent].children[ix]);
+                if let Some(previous) = previous {
+                    if let NodeKind::Text(existing) = &mut self.nodes[previous].kind {
+                        existing.push_str(&text);
+                        return
+                    }
+                }
+                let id = self.push_node(NodeKind::Text(text.to_string()));
+                self.nodes[id].parent = Some(parent);
+                self.nodes[parent].children.insert(index, id);
+            }
         }
     }
 }
@@ -119,8 +169,15 @@ impl TreeSink for FragmentSink {
         self.root_id
     }
 
-    fn get_template_contents(&mut self, _: &Self::Handle) -> Self::Handle {
-        self.root_id
+    fn get_template_contents(&mut self, target: &Self::Handle) -> Self::Handle {
+        if let NodeKind::Element { template_contents: Some(id), .. } = &self.nodes[*target].kind {
+            return *id
+        }
+        let contents = self.push_node(NodeKind::Fragment);
+        if let NodeKind::Element { template_contents, .. } = &mut self.nodes[*target].kind {
+            *template_contents = Some(contents);
+        }
+        contents
     }
 
     fn set_quirks_mode(&mut self, _: QuirksMode) {}
@@ -129,19 +186,10 @@ impl TreeSink for FragmentSink {
         x == y
     }
 
-    fn elem_name(&self, target: &Self::Handle) -> html5ever::ExpandedName {
-        use html5ever::ExpandedName;
-
-        if let NodeKind::Element(el) = &self.nodes[*target].kind {
-            ExpandedName {
-                ns: *NAMESPACE_HTML,
-                local: get_local(&el.tag),
-            }
-        } else {
-            ExpandedName {
-                ns: *NAMESPACE_EMPTY,
-                local: get_local(""),
-            }
+    fn elem_name<'a>(&'a self, target: &'a Self::Handle) -> ExpandedName<'a> {
+        match &self.nodes[*target].kind {
+            NodeKind::Element { name, .. } => name.expanded(),
+            _ => NON_ELEMENT_NAME.expanded(),
         }
     }
 
@@ -149,75 +197,230 @@ impl TreeSink for FragmentSink {
         &mut self,
         name: QualName,
         attrs: Vec<Attribute>,
-        _flags: ElementFlags,
+        flags: ElementFlags,
     ) -> Self::Handle {
-        let tag = name.local.to_string();
         let mut attr_map = HashMap::new();
         for attr in attrs {
-            attr_map.insert(attr.name.local.to_string(), attr.value.to_string());
+            attr_map.insert(attribute_name(&attr.name), attr.value.to_string());
         }
 
-        let el = Element {
-            tag,
-            attrs: attr_map,
-            children: vec![],
+        let position = match std::mem::take(&mut self.pending_context) {
+            true => None,
+            false => self.start_tags.claim(&name.local, self.current_line),
         };
-
-        let node = Node {
-            kind: NodeKind::Element(el),
+        let element = Element {
+            tag: name.local.to_string(),
+            attrs: attr_map,
             children: vec![],
-            parent: None,
+            position,
         };
 
-        self.nodes.push(node);
-        self.nodes.len() - 1
+        let id = self.push_node(NodeKind::Element {
+            name,
+            element,
+            template_contents: None,
+            mathml_annotation_xml_integration_point: flags.mathml_annotation_xml_integration_point,
+        });
+        if flags.template {
+            self.get_template_contents(&id);
+        }
+        id
     }
 
     fn create_comment(&mut self, _text: Tendril<UTF8>) -> Self::Handle {
-        // Safe dummy node — never rendered
-        let node = Node {
-            kind: NodeKind::Comment(String::new()),
-            children: vec![],
-            parent: None,
-        };
-
-        self.nodes.push(node);
-        self.nodes.len() - 1
+        self.push_node(NodeKind::Comment)
     }
 
     fn create_pi(&mut self, _target: Tendril<UTF8>, _data: Tendril<UTF8>) -> Self::Handle {
-        self.root_id
+        self.push_node(NodeKind::ProcessingInstruction)
     }
 
     fn append(&mut self, parent: &Self::Handle, child: NodeOrText<Self::Handle>) {
-        match child {
-            NodeOrText::AppendNode(id) => {
-                self.nodes[id].parent = Some(*parent);
-                self.nodes[*parent].children.push(id);
-            }
-            NodeOrText::AppendText(text) => {
-                // if text.chars().all(|c| c.is_whitespace()) {
-                //     return; // or keep if you want to preserve even all-whitespace nodes
-                // }
-                let node = Node {
-                    kind: NodeKind::Text(text.to_string()),
-                    children: vec![],
-                    parent: Some(*parent),
-                };
-                self.nodes.push(node);
-                let id = self.nodes.len() - 1;
-                self.nodes[*parent].children.push(id);
-            }
+        let index = self.nodes[*parent].children.len();
+        self.insert_at(*parent, index, child);
+    }
+
+    fn append_before_sibling(&mut self, sibling: &Self::Handle, new_node: NodeOrText<Self::Handle>) {
+        let Some(parent) = self.nodes[*sibling].parent else {
+            return
+        };
+        if let NodeOrText::AppendNode(id) = &new_node {
+            self.detach(*id);
+        }
+        let index = self.nodes[parent].children
+            .iter()
+            .position(|&child| child == *sibling)
+            .expect("sibling must be a child of its parent");
+        self.insert_at(parent, index, new_node);
+    }
+
+    fn append_based_on_parent_node(
+        &mut self,
+        element: &Self::Handle,
+        prev_element: &Self::Handle,
+        child: NodeOrText<Self::Handle>,
+    ) {
+        if self.nodes[*element].parent.is_some() {
+            self.append_before_sibling(element, child);
+        } else {
+            self.append(prev_element, child);
         }
     }
 
-    fn append_before_sibling(&mut self, _: &Self::Handle, _: NodeOrText<Self::Handle>) {}
-    fn append_based_on_parent_node(&mut self, _: &Self::Handle, _: &Self::Handle, _: NodeOrText<Self::Handle>) {}
     fn append_doctype_to_document(&mut self, _: Tendril<UTF8>, _: Tendril<UTF8>, _: Tendril<UTF8>) {}
-    fn add_attrs_if_missing(&mut self, _: &Self::Handle, _: Vec<Attribute>) {}
-    fn remove_from_parent(&mut self, _: &Self::Handle) {}
-    fn reparent_children(&mut self, _: &Self::Handle, _: &Self::Handle) {}
+
+    fn add_attrs_if_missing(&mut self, target: &Self::Handle, attrs: Vec<Attribute>) {
+        if let NodeKind::Element { element, .. } = &mut self.nodes[*target].kind {
+            for attr in attrs {
+                element.attrs
+                    .entry(attribute_name(&attr.name))
+                    .or_insert_with(|| attr.value.to_string());
+            }
+        }
+    }
+
+    fn remove_from_parent(&mut self, target: &Self::Handle) {
+        self.detach(*target);
+    }
+
+    fn reparent_children(&mut self, node: &Self::Handle, new_parent: &Self::Handle) {
+        let children = std::mem::take(&mut self.nodes[*node].children);
+        for child in children.iter() {
+            self.nodes[*child].parent = Some(*new_parent);
+        }
+        self.nodes[*new_parent].children.extend(children);
+    }
+
+    fn is_mathml_annotation_xml_integration_point(&self, handle: &Self::Handle) -> bool {
+        match &self.nodes[*handle].kind {
+            NodeKind::Element { mathml_annotation_xml_integration_point, .. } => *mathml_annotation_xml_integration_point,
+            _ => false,
+        }
+    }
+
     fn mark_script_already_started(&mut self, _: &Self::Handle) {}
+
+    fn set_current_line(&mut self, line_number: u64) {
+        self.current_line = line_number as usize;
+    }
+}
+
+/// Keeps the namespace prefix (e.g. `xlink:href`) so foreign attributes serialize back
+/// the way they were written. `xmlns` itself comes with an empty prefix.
+fn attribute_name(name: &QualName) -> String {
+    match &name.prefix {
+        Some(prefix) if !prefix.is_empty() => format!("{}:{}", prefix, name.local),
+        _ => name.local.to_string(),
+    }
+}
+
+// === Source Positions ===
+
+/// The tokenizer only reports line numbers (and only the line where a token ends), so
+/// start tag positions are recovered by scanning the source for `<tag` occurrences
+/// and handing them out in document order as elements are created.
+struct StartTagIndex {
+    occurrences: HashMap<String, VecDeque<SourcePosition>>,
+}
+
+/// Elements whose contents are never tokenized as markup.
+const RAW_TEXT_TAGS: &[&str] = &[
+    "script", "style", "textarea", "title", "xmp", "iframe", "noembed", "noframes", "plaintext",
+];
+
+impl StartTagIndex {
+    fn new(source: &str) -> Self {
+        let mut occurrences = HashMap::<String, VecDeque<SourcePosition>>::new();
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(ix, _)| ix + 1))
+            .collect::<Vec<_>>();
+        let position_of = |offset: usize| {
+            let line = line_starts.partition_point(|&start| start <= offset);
+            let line_start = line_starts[line - 1];
+            let column = source[line_start..offset].chars().count() + 1;
+            SourcePosition { line, column }
+        };
+        let lowered = source.to_ascii_lowercase();
+        let bytes = source.as_bytes();
+        let mut cursor = 0;
+        while let Some(found) = source[cursor..].find('<') {
+            let start = cursor + found;
+            let rest = &source[start..];
+            if rest.starts_with("<!--") {
+                cursor = source[start..]
+                    .find("-->")
+                    .map(|end| start + end + 3)
+                    .unwrap_or(source.len());
+                continue
+            }
+            let name_len = rest[1..]
+                .bytes()
+                .take_while(|b| b.is_ascii_alphanumeric() || *b == b'-' || *b == b':')
+                .count();
+            if name_len == 0 || !bytes[start + 1].is_ascii_alphabetic() {
+                cursor = start + 1;
+                continue
+            }
+            let name = lowered[start + 1..start + 1 + name_len].to_string();
+            occurrences.entry(name.clone()).or_default().push_back(position_of(start));
+            cursor = tag_end(source, start + 1 + name_len);
+            if RAW_TEXT_TAGS.contains(&name.as_str()) {
+                cursor = lowered[cursor..]
+                    .find(&format!("</{name}"))
+                    .map(|end| cursor + end)
+                    .unwrap_or(source.len());
+            }
+        }
+        StartTagIndex { occurrences }
+    }
+    /// Takes the next unclaimed `<tag` occurrence, provided it starts at or before the
+    /// line the tokenizer is on; otherwise the element was implied by the parser.
+    fn claim(&mut self, tag: &str, current_line: usize) -> Option<SourcePosition> {
+        let queue = self.occurrences.get_mut(&tag.to_ascii_lowercase())?;
+        match queue.front() {
+            Some(position) if position.line <= current_line => queue.pop_front(),
+            _ => None,
+        }
+    }
+}
+
+/// The offset just past the `>` closing a start tag whose attributes begin at `from`;
+/// a `>` (or `<`) inside a quoted attribute value doesn't count.
+fn tag_end(source: &str, from: usize) -> usize {
+    let mut quote = None;
+    for (ix, c) in source[from..].char_indices() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return from + ix + 1,
+            _ => (),
+        }
+    }
+    source.len()
+}
+
+/// Where the contents of the element whose start tag begins at `tag` start, i.e. just
+/// past the start tag's closing `>`.
+pub(crate) fn content_position(source: &str, tag: SourcePosition) -> Option<SourcePosition> {
+    let line_start = match tag.line {
+        0 => return None,
+        1 => 0,
+        line => source.match_indices('\n').nth(line - 2)?.0 + 1,
+    };
+    let start = line_start + source[line_start..]
+        .char_indices()
+        .nth(tag.column.checked_sub(1)?)?
+        .0;
+    let start_tag = &source[start..tag_end(source, start + 1)];
+    let position = match start_tag.rfind('\n') {
+        Some(ix) => SourcePosition {
+            line: tag.line + start_tag.matches('\n').count(),
+            column: start_tag[ix + 1..].chars().count() + 1,
+        },
+        None => SourcePosition { line: tag.line, column: tag.column + start_tag.chars().count() },
+    };
+    Some(position)
 }
 
 // === Public API ===
@@ -225,10 +428,10 @@ impl TreeSink for FragmentSink {
 /// Parses a raw HTML fragment into your custom `Html` structure.
 /// `context` is the tag name for the element under which parsing occurs (e.g. "div", "span", etc.)
 pub fn parse_html_fragment(input: &str, context: &str) -> Html {
-    // let context = QualName::new(None, *NAMESPACE_HTML, get_local(context).clone());
-    let context = QualName::new(None, (*NAMESPACE_HTML).clone(), get_local(context).clone());
+    let context = QualName::new(None, ns!(html), html5ever::LocalName::from(context));
 
-    let sink = FragmentSink::new();
+    let mut sink = FragmentSink::new(input);
+    sink.pending_context = true;
 
     let root = parse_fragment(sink, Default::default(), context, vec![])
         .from_utf8()
@@ -247,7 +450,7 @@ pub fn parse_html_fragment(input: &str, context: &str) -> Html {
 }
 
 pub fn parse_html_document(input: &str) -> Html {
-    let sink = FragmentSink::new();
+    let sink = FragmentSink::new(input);
 
     html5ever::parse_document(sink, Default::default())
         .from_utf8()
@@ -255,3 +458,95 @@ pub fn parse_html_document(input: &str) -> Html {
         .unwrap()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A compact rendering of the tree: `tag[name=value](children)`, text quoted.
+    fn shape(html: &Html) -> String {
+        match html {
+            Html::Text(text) => format!("{text:?}"),
+            Html::Fragment(nodes) => nodes.iter().map(shape).collect::<Vec<_>>().join(" "),
+            Html::Element(element) => {
+                let mut attrs = element.attrs.iter().collect::<Vec<_>>();
+                attrs.sort();
+                let attrs = attrs
+                    .iter()
+                    .map(|(key, value)| format!("[{key}={value}]"))
+                    .collect::<String>();
+                let children = element.children.iter().map(shape).collect::<Vec<_>>().join(" ");
+                format!("{}{attrs}({children})", element.tag)
+            }
+        }
+    }
+
+    fn position_of(html: &Html, tag: &str) -> Option<SourcePosition> {
+        match html {
+            Html::Text(_) => None,
+            Html::Fragment(nodes) => nodes.iter().find_map(|node| position_of(node, tag)),
+            Html::Element(element) if element.tag == tag => element.position,
+            Html::Element(element) => element.children.iter().find_map(|node| position_of(node, tag)),
+        }
+    }
+
+    #[test]
+    fn locates_start_tags() {
+        let html = parse_html_fragment("<div>\n  <p>x</p>\n</div>", "div");
+        assert_eq!(position_of(&html, "div"), Some(SourcePosition { line: 1, column: 1 }));
+        assert_eq!(position_of(&html, "p"), Some(SourcePosition { line: 2, column: 3 }));
+    }
+
+    #[test]
+    fn locates_start_tags_after_script_text() {
+        let html = parse_html_fragment("<script>if (a < b) {}</script>\n<b>x</b>", "div");
+        assert_eq!(position_of(&html, "b"), Some(SourcePosition { line: 2, column: 1 }));
+    }
+
+    #[test]
+    fn locates_start_tags_after_markup_in_attribute_values() {
+        let html = parse_html_fragment("<a title=\"1<b>2\" data-x='<i>'>y</a>\n<b>x</b> <i>z</i>", "div");
+        assert_eq!(position_of(&html, "b"), Some(SourcePosition { line: 2, column: 1 }));
+        assert_eq!(position_of(&html, "i"), Some(SourcePosition { line: 2, column: 10 }));
+    }
+
+    #[test]
+    fn locates_start_tags_after_comments_and_rcdata() {
+        let html = parse_html_fragment("<!-- <b> -->\n<textarea><b></textarea>\n<b>x</b>", "div");
+        assert_eq!(position_of(&html, "b"), Some(SourcePosition { line: 3, column: 1 }));
+    }
+
+    #[test]
+    fn foster_parents_table_text_and_merges_it() {
+        let html = parse_html_fragment("<table>A<tr><td>B</td></tr>C</table>", "div");
+        assert_eq!(shape(&html), r#""AC" table(tbody(tr(td("B"))))"#);
+    }
+
+    #[test]
+    fn foster_parents_elements_before_the_table() {
+        let html = parse_html_fragment("<table><b>x</b><tr><td>y</td></tr></table>", "div");
+        assert_eq!(shape(&html), r#"b("x") table(tbody(tr(td("y"))))"#);
+    }
+
+    #[test]
+    fn misnested_formatting_element_is_adopted() {
+        let html = parse_html_fragment("<b>1<p>2</b>3</p>", "div");
+        assert_eq!(shape(&html), r#"b("1") p(b("2") "3")"#);
+    }
+
+    #[test]
+    fn template_contents_become_children() {
+        let html = parse_html_fragment("<template><tr><td>x</td></tr></template>", "div");
+        assert_eq!(shape(&html), r#"template(tr(td("x")))"#);
+    }
+
+    #[test]
+    fn duplicate_html_and_body_only_add_missing_attributes() {
+        let html = parse_html_document(
+            r#"<html lang="en"><body class="a"><html lang="fr" dir="ltr"><body class="b" id="c">x"#,
+        );
+        assert_eq!(
+            shape(&html),
+            r#"html[dir=ltr][lang=en](head() body[class=a][id=c]("x"))"#,
+        );
+    }
+}
@@ -1,28 +1,133 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use crate::html::Html;
-use crate::html::Element;
 use crate::html::ParserMode;
+use crate::html::ParserBackend;
 
 use super::system::Scope;
 use super::system::State;
 
+/// Parsed and preprocessed files, shared by every page of a build.
+///
+/// A file is preprocessed in its own [`Scope`] — local paths are virtualized relative
+/// to the file itself, never to whoever includes it — so the result depends only on
+/// the file, how it's parsed and the project root, which is fixed for a build.
+/// Anything specific to the including page (its dependency on the file, the baked
+/// `<include>` children) is added after the lookup.
+///
+/// Concurrent first loads of the same file may each parse it; the first stored
+/// result wins.
+#[derive(Debug, Clone, Default)]
+pub struct ParseCache {
+    entries: Arc<Mutex<HashMap<ParseKey, State<Html>>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ParseKey {
+    file_path: PathBuf,
+    parser_mode: ParserMode,
+    parser_backend: ParserBackend,
+}
+
+impl ParseCache {
+    fn get(&self, key: &ParseKey) -> Option<State<Html>> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+    fn insert(&self, key: ParseKey, state: State<Html>) -> State<Html> {
+        self.entries.lock().unwrap().entry(key).or_insert(state).clone()
+    }
+}
+
+/// Loads and preprocesses a file with the settings of `loader`, the scope of whoever
+/// loads it; the file itself is preprocessed in a scope of its own.
 pub fn load_html_file(
     file_path: impl AsRef<Path>,
     parser_mode: ParserMode,
-    project_root: impl AsRef<Path>,
+    loader: &Scope,
 ) -> Result<State<Html>, Box<dyn std::error::Error>> {
     let file_path = path_clean::clean(file_path.as_ref().to_path_buf());
+    let project_root = path_clean::clean(&loader.project_root);
+    let parser_backend = loader.parser_backend;
+    let parse_cache = &loader.parse_cache;
+    let key = ParseKey { file_path: file_path.clone(), parser_mode: parser_mode.clone(), parser_backend };
+    if let Some(state) = parse_cache.get(&key) {
+        return Ok(state)
+    }
     let source = std::fs::read_to_string(&file_path)?;
-    let source_tree = Html::parse(&source, parser_mode);
+    let front_matter = crate::layout::split_front_matter(&source);
+    let source = front_matter.as_ref().map_or(&source, |(_, body)| body);
+    let mut source_tree = Html::parse_file(source, parser_mode, parser_backend, &file_path);
+    if source_tree.has_scoped_style() {
+        source_tree.mark_scoped_elements(&super::scoped_css::scope_attribute(&file_path, &project_root));
+    }
     let scope = Scope {
         source_path: file_path,
-        project_root: path_clean::clean(project_root.as_ref()),
+        project_root,
+        ..loader.clone()
+    };
+    let state = match front_matter {
+        Some((front_matter, _)) => {
+            crate::layout::apply_front_matter(source_tree, front_matter, &scope.source_path)
+                .and_then(|html| process_html_tree(html, &scope))
+        }
+        None => process_html_tree(source_tree, &scope),
     };
-    Ok(process_html_tree(source_tree, &scope))
+    Ok(parse_cache.insert(key, state))
 }
 
 fn process_html_tree(html: Html, scope: &Scope) -> State<Html> {
     html.preprocess(scope)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(file_path: &Path, parser_mode: ParserMode, parser_backend: ParserBackend, parse_cache: &ParseCache) -> String {
+        let loader = Scope {
+            project_root: file_path.parent().unwrap().to_path_buf(),
+            source_path: file_path.to_path_buf(),
+            parser_backend,
+            inline_threshold: None,
+            url_attributes: Vec::new(),
+            site_url: None,
+            parse_cache: parse_cache.clone(),
+        };
+        let state = load_html_file(file_path, parser_mode, &loader).unwrap();
+        state.value.to_text().unwrap()
+    }
+
+    #[test]
+    fn a_shared_include_is_parsed_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("shared.html"), "<nav>v1</nav>").unwrap();
+        std::fs::write(root.join("a.html"), "<h1>a</h1><include src=\"shared.html\"></include>").unwrap();
+        std::fs::write(root.join("b.html"), "<h1>b</h1><include src=\"shared.html\"></include>").unwrap();
+        let parse_cache = ParseCache::default();
+        let page = |name: &str| load(&root.join(name), ParserMode::fragment("body"), ParserBackend::Native, &parse_cache);
+        assert_eq!(page("a.html"), "av1");
+        // Had `b.html` parsed the include again, it would see the new contents.
+        std::fs::write(root.join("shared.html"), "<nav>v2</nav>").unwrap();
+        assert_eq!(page("b.html"), "bv1");
+        assert_eq!(parse_cache.entries.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn parses_are_cached_per_mode_and_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("page.html");
+        let parse_cache = ParseCache::default();
+        std::fs::write(&file_path, "<p>v1</p>").unwrap();
+        assert_eq!(load(&file_path, ParserMode::fragment("div"), ParserBackend::Native, &parse_cache), "v1");
+        std::fs::write(&file_path, "<p>v2</p>").unwrap();
+        assert_eq!(load(&file_path, ParserMode::fragment("div"), ParserBackend::Native, &parse_cache), "v1");
+        assert_eq!(load(&file_path, ParserMode::Document, ParserBackend::Native, &parse_cache), "v2");
+        assert_eq!(load(&file_path, ParserMode::fragment("div"), ParserBackend::RcDom, &parse_cache), "v2");
+        assert_eq!(load(&file_path, ParserMode::fragment("head"), ParserBackend::Native, &parse_cache), "v2");
+        assert_eq!(parse_cache.entries.lock().unwrap().len(), 4);
+    }
+}
@@ -2,3 +2,4 @@ pub mod system;
 pub mod preprocess;
 pub mod postprocess;
 pub mod load;
+pub mod scoped_css;
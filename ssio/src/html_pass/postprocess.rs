@@ -1,9 +1,12 @@
 use std::path::PathBuf;
 use crate::html::Html;
 use crate::html::Element;
+use crate::html_pass::system::Aggregator;
+use crate::html_pass::system::State;
 use crate::dependency_tracking::resolve_virtual_paths::PathResolver;
 use crate::dependency_tracking::resolve_virtual_paths::VirtualPathContext;
 use crate::dependency_tracking::resolve_virtual_paths::resolve_virtual_paths;
+use crate::dependency_tracking::data::UrlAttribute;
 // pub struct postprocess
 
 #[derive(Debug, Clone)]
@@ -14,52 +17,79 @@ pub struct PostprocessEnvironment {
     pub output_file_path: PathBuf,
     /// The virtual link resolver
     pub resolver: PathResolver,
+    /// Add `integrity` attributes to local scripts and stylesheets (see
+    /// [`crate::integrity`]).
+    pub subresource_integrity: bool,
+    /// Hash inline scripts and styles for the page's Content Security Policy (see
+    /// [`crate::csp`]).
+    pub content_security_policy: bool,
+    /// Extra URL attributes (see [`crate::dependency_tracking::data::url_kind`]).
+    pub url_attributes: Vec<UrlAttribute>,
+    /// See [`crate::compile::Compiler::site_url`].
+    pub site_url: Option<String>,
 }
 
 impl PostprocessEnvironment {
-    fn virtual_path_context(&self) -> VirtualPathContext {
+    pub(crate) fn virtual_path_context(&self) -> VirtualPathContext<'_> {
         VirtualPathContext {
             origin_file_path: &self.origin_file_path,
             output_file_path: &self.output_file_path,
             resolver: &self.resolver,
+            url_attributes: &self.url_attributes,
+            site_url: self.site_url.as_deref(),
         }
     }
 }
 
 impl Html {
-    pub fn postprocess(self, env: &PostprocessEnvironment) -> Self {
+    pub fn postprocess(self, env: &PostprocessEnvironment) -> State<Self> {
         match self {
-            Self::Element(element) => Self::Element(element.postprocess(env)),
-            Self::Text(text) => Self::Text(text),
-            Self::Fragment(nodes) => Self::Fragment(postprocess_fragment(nodes, env)),
+            Self::Element(element) => element.postprocess(env).map(Self::Element),
+            Self::Text(text) => State::wrap(Self::Text(text)),
+            Self::Fragment(nodes) => postprocess_fragment(nodes, env).map(Self::Fragment),
         }
     }
 }
 
 impl Element {
-    pub fn postprocess(self, env: &PostprocessEnvironment) -> Self {
-        let Element { tag, mut attrs, children } = self;
-        resolve_virtual_paths(&tag, &mut attrs, &env.virtual_path_context());
-        let children = postprocess_fragment(children, env);
-        let norm_tag = tag.to_lowercase();
-        match norm_tag.as_str() {
-            "style" => {
-                let source_code = Html::Fragment(children).to_text().unwrap();
-                let source_code = crate::css_process::post_process(&source_code, env);
-                let children = vec![
-                    Html::Text(source_code),
-                ];
-                return Element { tag, attrs, children }
-            }
-            _ => ()
+    pub fn postprocess(self, env: &PostprocessEnvironment) -> State<Self> {
+        let Element { tag, mut attrs, children, location } = self;
+        let mut aggregator = Aggregator::default();
+        resolve_virtual_paths(&tag, &mut attrs, location.as_ref(), &env.virtual_path_context(), &mut aggregator);
+        if env.subresource_integrity {
+            crate::integrity::add_integrity(&tag, &mut attrs, location.as_ref(), env, &mut aggregator);
         }
-        Element { tag, attrs, children }
+        let children = aggregator.wrap(()).and_then(|()| postprocess_fragment(children, env));
+        children.map_with(|children, ctx| {
+            let norm_tag = tag.to_lowercase();
+            let children = match norm_tag.as_str() {
+                "style" => {
+                    let source_code = Html::Fragment(children).to_text().unwrap();
+                    let source_code = crate::css_process::post_process(&source_code, location.as_ref(), env, ctx);
+                    vec![
+                        Html::Text(source_code),
+                    ]
+                }
+                _ => children
+            };
+            if env.content_security_policy {
+                if let Some(code) = crate::csp::inline_code(&tag, &attrs, &children) {
+                    let hash = crate::integrity::sha384(code.as_bytes());
+                    match norm_tag.as_str() {
+                        "style" => ctx.style_hashes.insert(hash),
+                        _ => ctx.script_hashes.insert(hash),
+                    };
+                }
+            }
+            Element { tag, attrs, children, location }
+        })
     }
 }
 
-fn postprocess_fragment(nodes: Vec<Html>, env: &PostprocessEnvironment) -> Vec<Html> {
-    nodes
+fn postprocess_fragment(nodes: Vec<Html>, env: &PostprocessEnvironment) -> State<Vec<Html>> {
+    let nodes_len = nodes.len();
+    let nodes = nodes
         .into_iter()
-        .map(|node| node.postprocess(env))
-        .collect()
+        .map(|node| node.postprocess(env));
+    State::flatten(nodes, Some(nodes_len))
 }
@@ -1,11 +1,8 @@
-use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
-};
+use std::collections::HashMap;
 
 use crate::{html::{Html, ParserMode}, html_pass::system::Dependency};
+use crate::diagnostics::{Diagnostic, SourceLocation};
 use crate::html::Element;
-use crate::compile::InputRule;
 use crate::html_pass::system::Scope;
 use crate::html_pass::system::State;
 use crate::html_pass::system::Aggregator;
@@ -30,22 +27,32 @@ impl Html {
 
 impl Element {
     pub fn preprocess(self, scope: &Scope) -> State<Html> {
-        let Element { tag, mut attrs, children } = self;
+        let Element { tag, mut attrs, children, location } = self;
         match &tag.to_lowercase()[..] {
             "include" => {
-                return process_include_tag(attrs, children, scope)
+                return process_include_tag(attrs, children, location, scope)
             }
             "style" => {
-                return process_style_tag(attrs, children, scope)
+                return process_style_tag(attrs, children, location, scope)
+            }
+            "img" if attrs.contains_key(crate::inline_assets::INLINE_ATTRIBUTE) => {
+                return crate::inline_assets::inline_svg(attrs, location, scope)
             }
             _ => ()
         }
         preprocess_fragment(children, scope).map_with(|children, ctx| {
+            if tag.eq_ignore_ascii_case("img") {
+                crate::inline_assets::inline_img_src(&mut attrs, scope, ctx);
+            }
+            if tag.eq_ignore_ascii_case("script") {
+                crate::modules::register_module_script(&attrs, scope, ctx);
+            }
             virtualize_and_register_local_paths(&tag, &mut attrs, scope, ctx);
             Html::Element(Element {
                 tag: tag,
                 attrs: attrs,
-                children: children
+                children: children,
+                location: location,
             })
         })
     }
@@ -62,6 +69,7 @@ fn preprocess_fragment(nodes: Vec<Html>, scope: &Scope) -> State<Vec<Html>> {
 fn process_include_tag(
     attrs: HashMap<String, String>,
     children: Vec<Html>,
+    location: Option<SourceLocation>,
     scope: &Scope,
 ) -> State<Html> {
     let content = preprocess_fragment(children, scope).map(|children| {
@@ -76,40 +84,53 @@ fn process_include_tag(
             is_internal: Some(true),
         };
         // - LOAD -
-        let template = super::load::load_html_file(
-            &resolved_path,
-            ParserMode::fragment("div"),
-            &scope.project_root,
-        );
-        let template = match template {
+        let template = super::load::load_html_file(&resolved_path, ParserMode::fragment("div"), scope);
+        let mut template = match template {
             Ok(x) => x,
             Err(error) => {
-                let source_path = scope.source_path.as_path();
-                if let Some(error) = error.downcast_ref::<std::io::Error>() {
-                    eprintln!("⚠️ {source_path:?} file not found: {resolved_path:?}");
+                let message = if error.downcast_ref::<std::io::Error>().is_some() {
+                    format!("included file not found: {resolved_path:?}")
                 } else {
-                    eprintln!("⚠️ {source_path:?}: {error}");
-                }
-                return State::wrap(Html::Fragment(Vec::default()))
+                    format!("failed to include {resolved_path:?}: {error}")
+                };
+                let diagnostic = Diagnostic::warning(message)
+                    .with_file(&scope.source_path)
+                    .with_location(location.as_ref());
+                // Still recorded, so the page is rebuilt once the file appears.
+                let mut aggregator = Aggregator::diagnostic(diagnostic);
+                aggregator.static_dependencies.insert(dependency);
+                return aggregator.wrap(Html::Fragment(Vec::default()))
             }
         };
+        for asset in crate::head::take_component_assets(&mut template.value) {
+            template.aggregator.add_component_asset(asset);
+        }
         let mut baked_node = crate::template::bake_template_content(template, content, false);
         baked_node.aggregator.static_dependencies.insert(dependency); // TODO: NOT A STATIC DEPENDENCY
         return baked_node
     }
-    eprintln!("⚠️ FAILED TO RESOLVE INCLUDE IN FILE: {:?}", scope.source_path);
-    content
+    content.map_with(|content, ctx| {
+        let diagnostic = Diagnostic::warning("<include> is missing a `src` attribute")
+            .with_file(&scope.source_path)
+            .with_location(location.as_ref());
+        ctx.diagnostics.push(diagnostic);
+        content
+    })
 }
 
 fn process_style_tag(
     mut attrs: HashMap<String, String>,
     children: Vec<Html>,
+    location: Option<SourceLocation>,
     scope: &Scope,
 ) -> State<Html> {
+    let scope_attribute = attrs
+        .remove(super::scoped_css::SCOPED_ATTRIBUTE)
+        .map(|_| super::scoped_css::scope_attribute(&scope.source_path, &scope.project_root));
     preprocess_fragment(children, scope).map_with(|children, ctx| {
         virtualize_and_register_local_paths("style", &mut attrs, scope, ctx);
         let source_code = Html::Fragment(children).to_text().unwrap();
-        let source_code = crate::css_process::pre_process(&source_code, scope, ctx);
+        let source_code = crate::css_process::pre_process(&source_code, location.as_ref(), scope, scope_attribute.as_deref(), ctx);
         let children = vec![
             Html::Text(source_code),
         ];
@@ -117,6 +138,7 @@ fn process_style_tag(
             tag: String::from("style"),
             attrs: attrs,
             children,
+            location,
         })
     })
 }
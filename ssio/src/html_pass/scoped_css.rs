@@ -0,0 +1,105 @@
+//! `<style scoped>`: styles that only apply to the file they're written in.
+//!
+//! A file with a scoped style gets an attribute unique to it, `data-ssio-…`, on every
+//! element it writes (not on what it includes), and the style's selectors are
+//! narrowed to elements with that attribute (see [`crate::css_process::pre_process`]).
+use std::path::Path;
+
+use crate::html::{Element, Html};
+
+pub const SCOPED_ATTRIBUTE: &str = "scoped";
+
+/// Elements that are never rendered as written, so never need the scope attribute.
+const UNSCOPED_TAGS: &[&str] = &["include", "content", "style", "script", "head", "title", "meta", "link", "base"];
+
+/// The attribute marking a file's elements: stable across builds and machines, since
+/// it's derived from the file's path relative to the project root.
+pub fn scope_attribute(source_path: &Path, project_root: &Path) -> String {
+    let relative = source_path.strip_prefix(project_root).unwrap_or(source_path);
+    let digest = crate::build_cache::fingerprint(&relative.to_string_lossy());
+    format!("data-ssio-{}", &digest[..8])
+}
+
+impl Html {
+    /// Whether the file contains a `<style scoped>`.
+    pub fn has_scoped_style(&self) -> bool {
+        match self {
+            Self::Element(element) => {
+                let is_scoped_style = element.tag.eq_ignore_ascii_case("style")
+                    && element.attrs.contains_key(SCOPED_ATTRIBUTE);
+                is_scoped_style || element.children.iter().any(Html::has_scoped_style)
+            }
+            Self::Fragment(nodes) => nodes.iter().any(Html::has_scoped_style),
+            Self::Text(_) => false,
+        }
+    }
+    /// Adds the (valueless) scope attribute to every rendered element.
+    pub fn mark_scoped_elements(&mut self, attribute: &str) {
+        match self {
+            Self::Element(element) => element.mark_scoped_elements(attribute),
+            Self::Fragment(nodes) => {
+                for node in nodes.iter_mut() {
+                    node.mark_scoped_elements(attribute);
+                }
+            }
+            Self::Text(_) => (),
+        }
+    }
+}
+
+impl Element {
+    fn mark_scoped_elements(&mut self, attribute: &str) {
+        let is_unscoped = UNSCOPED_TAGS.iter().any(|tag| self.tag.eq_ignore_ascii_case(tag));
+        if !is_unscoped {
+            self.attrs.insert(attribute.to_string(), String::default());
+        }
+        for child in self.children.iter_mut() {
+            child.mark_scoped_elements(attribute);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::ParserMode;
+
+    #[test]
+    fn scope_attribute_depends_only_on_the_relative_path() {
+        let attribute = scope_attribute(Path::new("/a/site/pages/index.html"), Path::new("/a/site"));
+        assert!(attribute.starts_with("data-ssio-"));
+        assert_eq!(attribute.len(), "data-ssio-".len() + 8);
+        assert_eq!(attribute, scope_attribute(Path::new("/b/site/pages/index.html"), Path::new("/b/site")));
+        assert_ne!(attribute, scope_attribute(Path::new("/a/site/pages/about.html"), Path::new("/a/site")));
+    }
+
+    #[test]
+    fn marks_rendered_elements_only() {
+        let source = r#"<style scoped>p {}</style><div><p>Hi</p><include src="card.html"></include><script></script></div>"#;
+        let mut html = Html::parse(source, ParserMode::fragment("div"), Default::default());
+        assert!(html.has_scoped_style());
+        html.mark_scoped_elements("data-ssio-x");
+        let mut marked = Vec::new();
+        collect_marked(&html, &mut marked);
+        assert_eq!(marked, ["div", "p"]);
+    }
+
+    #[test]
+    fn plain_styles_are_not_scoped() {
+        let html = Html::parse("<style>p {}</style><p>Hi</p>", ParserMode::fragment("div"), Default::default());
+        assert!(!html.has_scoped_style());
+    }
+
+    fn collect_marked(html: &Html, marked: &mut Vec<String>) {
+        match html {
+            Html::Element(element) => {
+                if element.attrs.contains_key("data-ssio-x") {
+                    marked.push(element.tag.clone());
+                }
+                element.children.iter().for_each(|child| collect_marked(child, marked));
+            }
+            Html::Fragment(nodes) => nodes.iter().for_each(|node| collect_marked(node, marked)),
+            Html::Text(_) => (),
+        }
+    }
+}
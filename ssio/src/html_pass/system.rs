@@ -1,25 +1,63 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{collections::{BTreeMap, BTreeSet, HashSet}, path::PathBuf};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+use crate::diagnostics::Diagnostic;
+use crate::html::ParserBackend;
+use crate::html_pass::load::ParseCache;
+use crate::dependency_tracking::data::UrlAttribute;
+
+#[derive(Debug, Clone)]
 pub struct Scope {
     pub project_root: PathBuf,
     pub source_path: PathBuf,
+    /// The parser used for this file, reused for anything it includes.
+    pub parser_backend: ParserBackend,
+    /// See [`crate::compile::Compiler::inline_threshold`].
+    pub inline_threshold: Option<u64>,
+    /// See [`crate::compile::Compiler::url_attributes`].
+    pub url_attributes: Vec<UrlAttribute>,
+    /// See [`crate::compile::Compiler::site_url`].
+    pub site_url: Option<String>,
+    /// Shared with every file loaded during the same build.
+    pub parse_cache: ParseCache,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Aggregator {
     pub source_dependencies: HashSet<Dependency>,
     pub static_dependencies: HashSet<Dependency>,
+    /// Files the page is compiled from without publishing them, such as inlined
+    /// assets (see [`crate::inline_assets`]).
     pub implicit_dependencies: HashSet<Dependency>,
+    /// Warnings and errors, in the order they were found.
+    pub diagnostics: Vec<Diagnostic>,
+    /// `<style>` and `<script>` elements taken out of included files, without
+    /// duplicates, in the order they were found.
+    pub component_assets: Vec<ComponentAsset>,
+    /// Hashes of inline scripts and styles, for the page's Content Security Policy
+    /// (see [`crate::csp`]).
+    pub script_hashes: BTreeSet<String>,
+    pub style_hashes: BTreeSet<String>,
+    /// Sources of local `<script type="module">`s, bundled when the build bundles
+    /// modules (see [`crate::modules`]).
+    pub module_scripts: HashSet<Dependency>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
 pub struct Dependency {
     pub origin: PathBuf,
     pub target: PathBuf,
     pub is_internal: Option<bool>,
 }
 
+/// A `<style>` or `<script>` from an included file; two are the same asset when their
+/// tag, attributes and code match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentAsset {
+    pub tag: String,
+    pub attrs: BTreeMap<String, String>,
+    pub code: String,
+}
+
 impl Scope {
     pub fn source_dir(&self) -> PathBuf {
         self.source_path.parent().unwrap().to_path_buf()
@@ -31,11 +69,20 @@ impl Aggregator {
         State { aggregator: self, value }
     }
     pub fn union(left: Self, right: Self) -> Self {
-        Aggregator {
+        let mut union = Aggregator {
             source_dependencies: left.source_dependencies.union(&right.source_dependencies).cloned().collect(),
             static_dependencies: left.static_dependencies.union(&right.static_dependencies).cloned().collect(),
             implicit_dependencies: left.implicit_dependencies.union(&right.implicit_dependencies).cloned().collect(),
+            diagnostics: left.diagnostics.into_iter().chain(right.diagnostics).collect(),
+            component_assets: left.component_assets,
+            script_hashes: left.script_hashes.union(&right.script_hashes).cloned().collect(),
+            style_hashes: left.style_hashes.union(&right.style_hashes).cloned().collect(),
+            module_scripts: left.module_scripts.union(&right.module_scripts).cloned().collect(),
+        };
+        for asset in right.component_assets {
+            union.add_component_asset(asset);
         }
+        union
     }
     pub fn merge(self, other: Self) -> Self {
         Self::union(self, other)
@@ -43,6 +90,23 @@ impl Aggregator {
     pub fn include(&mut self, other: Self) {
         self.source_dependencies.extend(other.source_dependencies);
         self.static_dependencies.extend(other.static_dependencies);
+        self.implicit_dependencies.extend(other.implicit_dependencies);
+        self.diagnostics.extend(other.diagnostics);
+        self.script_hashes.extend(other.script_hashes);
+        self.style_hashes.extend(other.style_hashes);
+        self.module_scripts.extend(other.module_scripts);
+        for asset in other.component_assets {
+            self.add_component_asset(asset);
+        }
+    }
+    pub fn add_component_asset(&mut self, asset: ComponentAsset) {
+        if !self.component_assets.contains(&asset) {
+            self.component_assets.push(asset);
+        }
+    }
+    /// An aggregator holding a single diagnostic.
+    pub fn diagnostic(diagnostic: Diagnostic) -> Self {
+        Aggregator { diagnostics: vec![diagnostic], ..Default::default() }
     }
     pub fn flatten(items: impl IntoIterator<Item=Aggregator>) -> Aggregator {
         let initial_state = Aggregator::default();
@@ -7,9 +7,6 @@ use crate::html::Element;
 // PRETTY PRINTER
 // ————————————————————————————————————————————————————————————————————————————
 
-#[derive(Debug, Clone)]
-pub struct PrettyPrinter {}
-
 #[derive(Debug, Clone)]
 pub struct Environment {
     indent: usize,
@@ -51,29 +48,6 @@ impl Environment {
             escape_tokens: escape_tokens,
         }
     }
-    pub fn indent(self) -> Environment {
-        Environment { indent: self.indent + 1, format_type: self.format_type, escape_tokens: self.escape_tokens }
-    }
-    pub fn inline(self) -> Environment {
-        Environment {
-            indent: self.indent,
-            format_type: FormatType::Inline,
-            escape_tokens: self.escape_tokens,
-        }
-    }
-    fn indent_spacing_string(&self) -> String {
-        indent_spacing_string(self.indent)
-    }
-    fn is_in_inline_mode(&self) -> bool {
-        self.format_type == FormatType::Inline
-    }
-    fn with_escape_tokens(self, escape_tokens: bool) -> Self {
-        Self {
-            indent: self.indent,
-            format_type: self.format_type,
-            escape_tokens: escape_tokens
-        }
-    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -127,7 +101,6 @@ impl Html {
 impl Element {
     pub fn html_string(&self, environment: &Environment) -> String {
         let environment = environment.scope(&self.tag);
-        let level = environment.indent_spacing_string();
         let attributes = format_attributes(&self.attrs);
         if crate::html::is_void_tag(&self.tag) && self.children.len() == 0 {
             format!(
@@ -163,9 +136,13 @@ fn format_fragment(nodes: &[Html], environment: &Environment) -> String {
     }
 }
 
+/// Attributes are written in name order: the parsed map has no order of its own, so
+/// anything else would differ from one build to the next.
 fn format_attributes(attributes: &HashMap<String, String>) -> String {
-    let mut attributes = attributes
-        .iter()
+    let mut attributes = attributes.iter().collect::<Vec<_>>();
+    attributes.sort_unstable_by_key(|(key, _)| key.as_str());
+    let attributes = attributes
+        .into_iter()
         .map(|(key, value)| {
             // println!("{key:?}: {value:?}");
             // if value.is_empty() {
@@ -181,14 +158,6 @@ fn format_attributes(attributes: &HashMap<String, String>) -> String {
     }
 }
 
-fn indent_spacing_string(level: usize) -> String {
-    if level == 0 {
-        String::from("")
-    } else {
-        std::iter::repeat(" ").take(level * 2).collect::<String>()
-    }
-}
-
 // ————————————————————————————————————————————————————————————————————————————
 // INTERNAL
 // ————————————————————————————————————————————————————————————————————————————
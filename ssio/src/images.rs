@@ -0,0 +1,440 @@
+//! Responsive images: resized variants for `<img responsive>`.
+//!
+//! After a page is postprocessed, every `<img>` with a `responsive` attribute gets
+//! variants of its image at each width (the attribute's space or comma separated
+//! widths, or [`DEFAULT_WIDTHS`]), never wider than the original, written to
+//! `output_dir/_images`. The image's `srcset` lists them, `sizes` defaults to `100vw`,
+//! and `width`/`height` are set to the original's dimensions so the browser can
+//! reserve space before it loads.
+//!
+//! Variants keep the original's format (JPEG, or PNG for anything else). PNGs also
+//! get lossless WebP variants, and with the `avif` feature every image gets AVIF ones;
+//! these are offered through `<source>`s of a `<picture>` wrapped around the `<img>`.
+//! Variant names include a hash of the original, so an existing variant is up to date
+//! and is never re-encoded, and the build can plan them (for `--dry-run`, collision
+//! checks and pruning) before anything is written.
+use std::collections::HashMap;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::dependency_tracking::data::SrcsetCandidate;
+use crate::diagnostics::Diagnostic;
+use crate::html::{Element, Html};
+use crate::html_pass::system::Aggregator;
+
+pub const RESPONSIVE_ATTRIBUTE: &str = "responsive";
+
+/// Variant widths for a bare `<img responsive>`.
+pub const DEFAULT_WIDTHS: &[u32] = &[480, 960, 1440, 1920];
+
+/// Where variants are written, relative to the output directory.
+pub const IMAGE_OUTPUT_DIR: &str = "_images";
+
+const JPEG_QUALITY: u8 = 82;
+#[cfg(feature = "avif")]
+const AVIF_QUALITY: u8 = 70;
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 8;
+
+/// Part of every variant name's hash; bump when encoding settings change.
+const ENCODING_VERSION: &str = "1";
+
+/// A resized copy of an image, written into the output directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageVariant {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub width: u32,
+    /// The variant's MIME type.
+    pub mime_type: String,
+}
+
+/// Every variant of one image, for one set of widths.
+#[derive(Debug, Clone)]
+struct ImageSet {
+    width: u32,
+    height: u32,
+    /// The MIME type of the `<img>`'s own `srcset`.
+    mime_type: String,
+    /// The original's format first, then any others, each narrowest first.
+    variants: Vec<ImageVariant>,
+    /// Whether the original is the widest variant in its own format, and so isn't
+    /// among `variants`.
+    includes_original: bool,
+}
+
+type ImageKey = (PathBuf, Vec<u32>);
+
+/// Filled by whichever page asks for the image first.
+type ImageSlot = Arc<OnceLock<Result<ImageSet, String>>>;
+
+/// Generates variants, each image (and set of widths) once per build, however many
+/// pages use it.
+#[derive(Debug, Clone)]
+pub struct ImageProcessor {
+    output_dir: PathBuf,
+    sets: Arc<Mutex<HashMap<ImageKey, ImageSlot>>>,
+}
+
+impl ImageProcessor {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        ImageProcessor { output_dir: output_dir.into(), sets: Arc::default() }
+    }
+    /// Replaces every `<img responsive>` in a postprocessed page, written to
+    /// `output_file_path`; returns the page and the variants it uses.
+    pub fn process_page(&self, html: Html, origin_file_path: &Path, output_file_path: &Path, aggregator: &mut Aggregator) -> (Html, Vec<ImageVariant>) {
+        let mut variants = Vec::new();
+        let mut page = PageImages { processor: self, origin_file_path, output_file_path, aggregator, variants: &mut variants };
+        let html = page.process(html);
+        (html, variants)
+    }
+    fn image_set(&self, source: &Path, widths: Vec<u32>) -> Result<ImageSet, String> {
+        let entry = self.sets
+            .lock()
+            .unwrap()
+            .entry((source.to_path_buf(), widths.clone()))
+            .or_default()
+            .clone();
+        entry
+            .get_or_init(|| self.generate(source, &widths))
+            .clone()
+    }
+    /// The variants of every `<img responsive>` in a loaded (not yet postprocessed)
+    /// page, named as the build will write them; nothing is encoded.
+    pub fn plan_page(&self, html: &Html, project_root: &Path) -> Vec<ImageVariant> {
+        let mut images = Vec::new();
+        collect_responsive_images(html, &mut images);
+        images
+            .into_iter()
+            .filter_map(|element| {
+                let widths = requested_widths(element).ok()?;
+                let src = element.attrs.get("src")?.split(['?', '#']).next()?;
+                let source = project_root.join(src.strip_prefix("@/")?);
+                let source = std::fs::canonicalize(path_clean::clean(source)).ok()?;
+                let bytes = std::fs::read(&source).ok()?;
+                self.plan(&source, &bytes, &widths).ok()
+            })
+            .flat_map(|(set, _)| set.variants)
+            .collect()
+    }
+    /// Names the variants of an image without writing them.
+    fn plan(&self, source: &Path, bytes: &[u8], widths: &[u32]) -> Result<(ImageSet, ImageFormat), String> {
+        let format = image::guess_format(bytes).map_err(|error| error.to_string())?;
+        let (width, height) = image::ImageReader::with_format(std::io::Cursor::new(bytes), format)
+            .into_dimensions()
+            .map_err(|error| error.to_string())?;
+        let digest = crate::build_cache::hex_digest(&[ENCODING_VERSION.as_bytes(), bytes].concat());
+        let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+        let mut widths = widths
+            .iter()
+            .copied()
+            .filter(|candidate| *candidate < width)
+            .chain([width])
+            .collect::<Vec<_>>();
+        widths.sort();
+        widths.dedup();
+        let mut variants = Vec::new();
+        let fallback_format = output_formats(format)[0];
+        let includes_original = fallback_format == format;
+        for output_format in output_formats(format) {
+            for variant_width in widths.iter().copied() {
+                if includes_original && output_format == format && variant_width == width {
+                    continue
+                }
+                let file_name = format!(
+                    "{stem}-{}-{variant_width}w.{}",
+                    &digest[..8],
+                    output_format.extensions_str()[0],
+                );
+                variants.push(ImageVariant {
+                    source: source.to_path_buf(),
+                    output: self.output_dir.join(IMAGE_OUTPUT_DIR).join(file_name),
+                    width: variant_width,
+                    mime_type: output_format.to_mime_type().to_string(),
+                });
+            }
+        }
+        let mime_type = fallback_format.to_mime_type().to_string();
+        Ok((ImageSet { width, height, mime_type, variants, includes_original }, format))
+    }
+    fn generate(&self, source: &Path, widths: &[u32]) -> Result<ImageSet, String> {
+        let bytes = std::fs::read(source).map_err(|error| error.to_string())?;
+        let (set, format) = self.plan(source, &bytes, widths)?;
+        let missing = set.variants
+            .iter()
+            .filter(|variant| !variant.output.exists())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(set)
+        }
+        let image = image::load_from_memory_with_format(&bytes, format).map_err(|error| error.to_string())?;
+        for variant in missing {
+            let output = &variant.output;
+            let output_format = ImageFormat::from_mime_type(&variant.mime_type).unwrap();
+            let resized = match variant.width {
+                variant_width if variant_width == set.width => image.clone(),
+                variant_width => {
+                    let variant_height = (set.height as u64 * variant_width as u64 / set.width as u64).max(1) as u32;
+                    image.resize_exact(variant_width, variant_height, image::imageops::FilterType::Lanczos3)
+                }
+            };
+            encode(&resized, output_format, output)
+                .map_err(|error| format!("failed to write {output:?}: {error}"))?;
+        }
+        Ok(set)
+    }
+}
+
+/// The original's format (JPEG stays JPEG, anything else becomes PNG), then the
+/// modern formats worth offering for it.
+fn output_formats(source_format: ImageFormat) -> Vec<ImageFormat> {
+    let fallback = match source_format {
+        ImageFormat::Jpeg => ImageFormat::Jpeg,
+        _ => ImageFormat::Png,
+    };
+    #[allow(unused_mut)]
+    let mut formats = vec![fallback];
+    #[cfg(feature = "avif")]
+    formats.push(ImageFormat::Avif);
+    // The pure Rust WebP encoder is lossless: a win over PNG, not over JPEG.
+    if fallback == ImageFormat::Png {
+        formats.push(ImageFormat::WebP);
+    }
+    formats
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat, path: &Path) -> image::ImageResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = BufWriter::new(std::fs::File::create(path)?);
+    match format {
+        ImageFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut file, JPEG_QUALITY);
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)
+        }
+        #[cfg(feature = "avif")]
+        ImageFormat::Avif => {
+            let encoder = image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut file, AVIF_SPEED, AVIF_QUALITY);
+            image.write_with_encoder(encoder)
+        }
+        ImageFormat::WebP => DynamicImage::ImageRgba8(image.to_rgba8()).write_to(&mut file, format),
+        _ => image.write_to(&mut file, format),
+    }
+}
+
+/// One page's pass over its `<img responsive>` elements.
+struct PageImages<'a> {
+    processor: &'a ImageProcessor,
+    origin_file_path: &'a Path,
+    output_file_path: &'a Path,
+    aggregator: &'a mut Aggregator,
+    variants: &'a mut Vec<ImageVariant>,
+}
+
+impl PageImages<'_> {
+    fn process(&mut self, html: Html) -> Html {
+        match html {
+            Html::Element(element) if is_responsive_image(&element) => self.process_image(element),
+            Html::Element(mut element) => {
+                element.children = element.children
+                    .into_iter()
+                    .map(|child| self.process(child))
+                    .collect();
+                Html::Element(element)
+            }
+            Html::Fragment(nodes) => Html::Fragment(nodes.into_iter().map(|node| self.process(node)).collect()),
+            Html::Text(text) => Html::Text(text),
+        }
+    }
+    fn process_image(&mut self, mut element: Element) -> Html {
+        let widths = requested_widths(&element);
+        let requested = element.attrs.remove(RESPONSIVE_ATTRIBUTE).unwrap_or_default();
+        let Ok(widths) = widths else {
+            self.warn(&element, format!("invalid `responsive` widths {requested:?}; expected pixel widths like \"480 960\""));
+            return Html::Element(element)
+        };
+        let Some(source) = self.resolve_src(&element) else {
+            self.warn(&element, "`<img responsive>` needs the `src` of a local image");
+            return Html::Element(element)
+        };
+        let set = match self.processor.image_set(&source, widths) {
+            Ok(set) => set,
+            Err(error) => {
+                self.warn(&element, format!("failed to make responsive variants of {source:?}: {error}"));
+                return Html::Element(element)
+            }
+        };
+        self.variants.extend(set.variants.iter().cloned());
+        let candidates_of = |mime_type: &str| {
+            set.variants
+                .iter()
+                .filter(|variant| variant.mime_type == mime_type)
+                .map(|variant| SrcsetCandidate {
+                    url: self.relative_url(&variant.output),
+                    descriptor: Some(format!("{}w", variant.width)),
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut candidates = candidates_of(&set.mime_type);
+        if set.includes_original {
+            candidates.push(SrcsetCandidate {
+                url: element.attrs["src"].clone(),
+                descriptor: Some(format!("{}w", set.width)),
+            });
+        }
+        let sizes = element.attrs
+            .entry(String::from("sizes"))
+            .or_insert_with(|| String::from("100vw"))
+            .clone();
+        element.attrs.insert(String::from("srcset"), SrcsetCandidate::format_srcset(&candidates));
+        if !element.attrs.contains_key("width") && !element.attrs.contains_key("height") {
+            element.attrs.insert(String::from("width"), set.width.to_string());
+            element.attrs.insert(String::from("height"), set.height.to_string());
+        }
+        let mut other_types = set.variants
+            .iter()
+            .map(|variant| variant.mime_type.clone())
+            .filter(|mime_type| *mime_type != set.mime_type)
+            .collect::<Vec<_>>();
+        other_types.dedup();
+        if other_types.is_empty() {
+            return Html::Element(element)
+        }
+        let sources = other_types.iter().map(|mime_type| {
+            let attrs = HashMap::from([
+                (String::from("type"), mime_type.clone()),
+                (String::from("srcset"), SrcsetCandidate::format_srcset(&candidates_of(mime_type))),
+                (String::from("sizes"), sizes.clone()),
+            ]);
+            Html::Element(Element { tag: String::from("source"), attrs, children: Vec::default(), location: None })
+        });
+        let location = element.location.clone();
+        Html::Element(Element {
+            tag: String::from("picture"),
+            attrs: HashMap::default(),
+            children: sources.chain([Html::Element(element)]).collect(),
+            location,
+        })
+    }
+    /// The file behind a (postprocessed, so output relative) `src`.
+    fn resolve_src(&self, element: &Element) -> Option<PathBuf> {
+        let src = element.attrs.get("src")?;
+        let src = src.split(['?', '#']).next().unwrap_or_default();
+        if src.is_empty() || crate::path_utils::is_external_url(src) {
+            return None
+        }
+        let output_dir = &self.processor.output_dir;
+        let path = match src.strip_prefix('/') {
+            Some(src) => output_dir.join(src),
+            None => self.output_file_path.parent()?.join(src),
+        };
+        // Assets are symlinked into the output directory; variants are keyed by the
+        // file behind them.
+        std::fs::canonicalize(path_clean::clean(path)).ok().filter(|path| path.is_file())
+    }
+    fn relative_url(&self, output: &Path) -> String {
+        let page_dir = self.output_file_path.parent().unwrap_or(&self.processor.output_dir);
+        pathdiff::diff_paths(output, page_dir)
+            .unwrap_or_else(|| output.to_path_buf())
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+    fn warn(&mut self, element: &Element, message: impl Into<String>) {
+        let diagnostic = Diagnostic::warning(message)
+            .with_file(self.origin_file_path)
+            .with_location(element.location.as_ref());
+        self.aggregator.diagnostics.push(diagnostic);
+    }
+}
+
+fn is_responsive_image(element: &Element) -> bool {
+    element.tag.eq_ignore_ascii_case("img") && element.attrs.contains_key(RESPONSIVE_ATTRIBUTE)
+}
+
+/// The widths an `<img responsive>` asks for, or [`DEFAULT_WIDTHS`].
+fn requested_widths(element: &Element) -> Result<Vec<u32>, std::num::ParseIntError> {
+    let requested = element.attrs.get(RESPONSIVE_ATTRIBUTE).map(String::as_str).unwrap_or_default();
+    let widths = requested
+        .split([',', ' '])
+        .filter(|width| !width.is_empty())
+        .map(str::parse::<u32>)
+        .collect::<Result<Vec<_>, _>>()?;
+    match widths.is_empty() {
+        true => Ok(DEFAULT_WIDTHS.to_vec()),
+        false => Ok(widths),
+    }
+}
+
+fn collect_responsive_images<'a>(html: &'a Html, images: &mut Vec<&'a Element>) {
+    match html {
+        Html::Element(element) if is_responsive_image(element) => images.push(element),
+        Html::Element(element) => {
+            for child in element.children.iter() {
+                collect_responsive_images(child, images);
+            }
+        }
+        Html::Fragment(nodes) => {
+            for node in nodes.iter() {
+                collect_responsive_images(node, images);
+            }
+        }
+        Html::Text(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::ParserMode;
+
+    #[test]
+    fn planned_variants_are_the_ones_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let output_dir = root.join("out");
+        std::fs::create_dir_all(root.join("img")).unwrap();
+        std::fs::create_dir_all(output_dir.join("img")).unwrap();
+        DynamicImage::new_rgb8(600, 300).save_with_format(root.join("img/a.png"), ImageFormat::Png).unwrap();
+        std::os::unix::fs::symlink(root.join("img/a.png"), output_dir.join("img/a.png")).unwrap();
+        let processor = ImageProcessor::new(&output_dir);
+
+        let loaded = Html::parse(r#"<img responsive="200 400 800" src="@/img/a.png">"#, ParserMode::fragment("div"), Default::default());
+        let planned = processor.plan_page(&loaded, &root);
+        let names = planned
+            .iter()
+            .filter(|variant| variant.mime_type != "image/avif")
+            .map(|variant| variant.output.strip_prefix(&output_dir).unwrap().to_path_buf())
+            .map(|path| (path.extension().unwrap().to_string_lossy().to_string(), variant_width(&path)))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [
+            (String::from("png"), 200), (String::from("png"), 400),
+            (String::from("webp"), 200), (String::from("webp"), 400), (String::from("webp"), 600),
+        ]);
+        assert!(planned.iter().all(|variant| !variant.output.exists()));
+
+        let postprocessed = Html::parse(r#"<img responsive="200 400 800" src="img/a.png">"#, ParserMode::fragment("div"), Default::default());
+        let mut aggregator = Aggregator::default();
+        let (_, written) = processor.process_page(postprocessed, &root.join("index.html"), &output_dir.join("index.html"), &mut aggregator);
+        assert!(aggregator.diagnostics.is_empty(), "{:?}", aggregator.diagnostics);
+        assert_eq!(written, planned);
+        assert!(written.iter().all(|variant| variant.output.is_file()));
+    }
+
+    #[test]
+    fn images_that_cannot_be_read_plan_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let processor = ImageProcessor::new(dir.path().join("out"));
+        let loaded = Html::parse(r#"<img responsive src="@/missing.png"><img responsive="wide" src="@/a.png">"#, ParserMode::fragment("div"), Default::default());
+        assert!(processor.plan_page(&loaded, dir.path()).is_empty());
+    }
+
+    fn variant_width(path: &Path) -> u32 {
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        stem.rsplit('-').next().unwrap().trim_end_matches('w').parse().unwrap()
+    }
+}
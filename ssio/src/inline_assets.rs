@@ -0,0 +1,264 @@
+//! Inlining small assets into the pages that use them.
+//!
+//! With an inline threshold set (see [`crate::compile::Compiler::inline_threshold`]),
+//! an image or font referenced by `<img src>` or a style's `url()` that is no larger
+//! than the threshold is embedded as a base64 data URI instead of being published and
+//! linked. `<img inline src="icon.svg">` goes further and replaces the `<img>` with the
+//! SVG's own markup, whatever its size, so it can be styled like the rest of the page.
+//!
+//! Inlined files are recorded as implicit asset dependencies: pages are rebuilt when
+//! they change, but they're only published when something else links them. A `src`
+//! starting with `/` is resolved against the project root.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+
+use crate::diagnostics::{Diagnostic, SourceLocation};
+use crate::html::{Element, Html, ParserMode};
+use crate::html_pass::system::{Aggregator, Dependency, Scope, State};
+
+pub const INLINE_ATTRIBUTE: &str = "inline";
+
+/// What may be inlined as a data URI, by extension.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+];
+
+/// `<img>` attributes that describe the image file rather than the element, so aren't
+/// carried over to an inlined SVG.
+const IMG_ONLY_ATTRIBUTES: &[&str] = &["src", "srcset", "sizes", "alt", "loading", "decoding", "fetchpriority", "crossorigin"];
+
+/// The data URI for a local file the threshold allows, registering the file as an
+/// implicit dependency. `None` leaves the reference to be linked as usual; so does a
+/// `#fragment`, which would be lost.
+pub fn try_data_uri(href: &str, scope: &Scope, aggregator: &mut Aggregator) -> Option<String> {
+    let threshold = scope.inline_threshold?;
+    if crate::path_utils::is_external_url(href) || href.contains('#') {
+        return None
+    }
+    let path = href.split('?').next().unwrap_or_default();
+    let mime_type = mime_type(Path::new(path))?;
+    let resolved = resolve_src(path, scope);
+    if std::fs::metadata(&resolved).ok()?.len() > threshold {
+        return None
+    }
+    let bytes = std::fs::read(&resolved).ok()?;
+    aggregator.implicit_dependencies.insert(asset_dependency(path, scope));
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+    Some(format!("data:{mime_type};base64,{encoded}"))
+}
+
+/// Inlines an `<img>`'s `src`, unless the image is responsive (its variants need the
+/// file).
+pub fn inline_img_src(attrs: &mut HashMap<String, String>, scope: &Scope, aggregator: &mut Aggregator) {
+    if attrs.contains_key(crate::images::RESPONSIVE_ATTRIBUTE) {
+        return
+    }
+    let Some(src) = attrs.get("src") else {
+        return
+    };
+    if let Some(data_uri) = try_data_uri(src, scope, aggregator) {
+        attrs.insert(String::from("src"), data_uri);
+    }
+}
+
+/// Replaces an `<img inline>` with the `<svg>` of its `src`, carrying over the
+/// element's own attributes; `alt` becomes the SVG's accessible name. Anything that
+/// isn't a local SVG is warned about and left a regular `<img>`.
+pub fn inline_svg(mut attrs: HashMap<String, String>, location: Option<SourceLocation>, scope: &Scope) -> State<Html> {
+    attrs.remove(INLINE_ATTRIBUTE);
+    let src = attrs.get("src").cloned().unwrap_or_default();
+    let svg = match load_svg(&src, scope) {
+        Ok(svg) => svg,
+        Err(message) => {
+            let diagnostic = Diagnostic::warning(message)
+                .with_file(&scope.source_path)
+                .with_location(location.as_ref());
+            let img = Element { tag: String::from("img"), attrs, children: Vec::default(), location };
+            let mut aggregator = Aggregator::diagnostic(diagnostic);
+            if mime_type(Path::new(&src)) == Some("image/svg+xml") && !crate::path_utils::is_external_url(&src) {
+                // Still recorded, so the page is rebuilt once the file appears.
+                aggregator.implicit_dependencies.insert(asset_dependency(&src, scope));
+            }
+            return aggregator.wrap(()).and_then(|()| img.preprocess(scope))
+        }
+    };
+    svg.map(|mut svg| {
+        merge_img_attributes(&mut svg, attrs);
+        Html::Element(svg)
+    })
+}
+
+fn load_svg(src: &str, scope: &Scope) -> Result<State<Element>, String> {
+    if src.is_empty() || crate::path_utils::is_external_url(src) {
+        return Err(String::from("`<img inline>` needs the `src` of a local SVG"))
+    }
+    if mime_type(Path::new(src)) != Some("image/svg+xml") {
+        return Err(format!("`<img inline>` only inlines SVGs: {src:?}"))
+    }
+    let resolved = resolve_src(src, scope);
+    let svg = crate::html_pass::load::load_html_file(&resolved, ParserMode::fragment("div"), scope);
+    let mut svg = svg.map_err(|_| format!("inlined SVG not found: {resolved:?}"))?;
+    let Some(element) = take_svg_element(&mut svg.value) else {
+        return Err(format!("no <svg> element in {resolved:?}"))
+    };
+    svg.aggregator.implicit_dependencies.insert(asset_dependency(src, scope));
+    Ok(svg.aggregator.wrap(element))
+}
+
+/// The first top-level `<svg>` (past any XML declaration, doctype or comments).
+fn take_svg_element(html: &mut Html) -> Option<Element> {
+    match html {
+        Html::Element(element) if element.tag.eq_ignore_ascii_case("svg") => {
+            Some(std::mem::replace(element, Element {
+                tag: String::default(),
+                attrs: HashMap::default(),
+                children: Vec::default(),
+                location: None,
+            }))
+        }
+        Html::Fragment(nodes) => nodes.iter_mut().find_map(take_svg_element),
+        _ => None,
+    }
+}
+
+fn merge_img_attributes(svg: &mut Element, attrs: HashMap<String, String>) {
+    for (key, value) in attrs {
+        match key.as_str() {
+            "alt" if value.trim().is_empty() => {
+                svg.attrs.insert(String::from("aria-hidden"), String::from("true"));
+            }
+            "alt" => {
+                svg.attrs.insert(String::from("role"), String::from("img"));
+                svg.attrs.insert(String::from("aria-label"), value);
+            }
+            "class" | "style" => {
+                let separator = if key == "class" { " " } else { "; " };
+                let merged = match svg.attrs.get(&key) {
+                    Some(existing) if !existing.trim().is_empty() => format!("{existing}{separator}{value}"),
+                    _ => value,
+                };
+                svg.attrs.insert(key, merged);
+            }
+            _ if IMG_ONLY_ATTRIBUTES.contains(&key.as_str()) => (),
+            _ => {
+                svg.attrs.insert(key, value);
+            }
+        }
+    }
+}
+
+fn mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    MIME_TYPES
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// The file behind a local `src`, relative to the file it's written in or, with a
+/// leading `/`, to the project root.
+fn resolve_src(src: &str, scope: &Scope) -> PathBuf {
+    match src.strip_prefix('/') {
+        Some(src) => scope.project_root.join(src),
+        None => scope.source_dir().join(src),
+    }
+}
+
+fn asset_dependency(href: &str, scope: &Scope) -> Dependency {
+    let target = match href.starts_with('/') {
+        true => resolve_src(href, scope),
+        false => PathBuf::from(href),
+    };
+    Dependency {
+        origin: path_clean::clean(&scope.source_path),
+        target: path_clean::clean(target),
+        is_internal: Some(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(root: &Path, inline_threshold: Option<u64>) -> Scope {
+        Scope {
+            project_root: root.to_path_buf(),
+            source_path: root.join("pages/index.html"),
+            parser_backend: Default::default(),
+            inline_threshold,
+            url_attributes: Vec::new(),
+            site_url: None,
+            parse_cache: Default::default(),
+        }
+    }
+
+    fn resolved(dependency: &Dependency) -> PathBuf {
+        path_clean::clean(dependency.origin.parent().unwrap().join(&dependency.target))
+    }
+
+    fn project() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("pages")).unwrap();
+        std::fs::create_dir_all(dir.path().join("img")).unwrap();
+        std::fs::write(dir.path().join("img/dot.png"), b"png").unwrap();
+        std::fs::write(dir.path().join("img/logo.svg"), r#"<svg viewBox="0 0 1 1"><path d="M0 0"/></svg>"#).unwrap();
+        dir
+    }
+
+    #[test]
+    fn small_files_become_implicit_asset_dependencies() {
+        let dir = project();
+        let scope = scope(dir.path(), Some(16));
+        let mut aggregator = Aggregator::default();
+        let data_uri = try_data_uri("../img/dot.png", &scope, &mut aggregator);
+        assert_eq!(data_uri.as_deref(), Some("data:image/png;base64,cG5n"));
+        assert!(aggregator.static_dependencies.is_empty());
+        let dependency = aggregator.implicit_dependencies.iter().next().unwrap();
+        assert_eq!(dependency.is_internal, Some(false));
+        assert_eq!(resolved(dependency), dir.path().join("img/dot.png"));
+    }
+
+    #[test]
+    fn root_absolute_sources_resolve_against_the_project_root() {
+        let dir = project();
+        let scope = scope(dir.path(), Some(16));
+        let mut aggregator = Aggregator::default();
+        let data_uri = try_data_uri("/img/dot.png", &scope, &mut aggregator);
+        assert_eq!(data_uri.as_deref(), Some("data:image/png;base64,cG5n"));
+        let dependency = aggregator.implicit_dependencies.iter().next().unwrap();
+        assert_eq!(resolved(dependency), dir.path().join("img/dot.png"));
+
+        let attrs = HashMap::from([(String::from("src"), String::from("/img/logo.svg"))]);
+        let State { aggregator, value: svg } = inline_svg(attrs, None, &scope);
+        assert!(aggregator.diagnostics.is_empty(), "{:?}", aggregator.diagnostics);
+        assert!(matches!(&svg, Html::Element(element) if element.tag == "svg"));
+        let targets = aggregator.implicit_dependencies
+            .iter()
+            .map(resolved)
+            .collect::<Vec<_>>();
+        assert_eq!(targets, [dir.path().join("img/logo.svg")]);
+    }
+
+    #[test]
+    fn files_over_the_threshold_are_not_inlined() {
+        let dir = project();
+        let mut aggregator = Aggregator::default();
+        assert_eq!(try_data_uri("/img/dot.png", &scope(dir.path(), Some(2)), &mut aggregator), None);
+        assert_eq!(try_data_uri("/img/dot.png", &scope(dir.path(), None), &mut aggregator), None);
+        assert_eq!(try_data_uri("/img/dot.png#x", &scope(dir.path(), Some(16)), &mut aggregator), None);
+        assert!(aggregator.implicit_dependencies.is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+//! Subresource Integrity: `integrity` hashes for local scripts and stylesheets.
+//!
+//! With [`crate::compile::Compiler::subresource_integrity`] on, every `<script src>`
+//! and `<link rel="stylesheet" href>` pointing at a local file gets an `integrity`
+//! attribute with the SHA-384 of the file as published (a bundle, for bundled modules).
+//! An `integrity` written by hand is kept.
+use std::collections::HashMap;
+
+use base64::Engine;
+use sha2::{Digest, Sha384};
+
+use crate::diagnostics::{Diagnostic, SourceLocation};
+use crate::html_pass::postprocess::PostprocessEnvironment;
+use crate::html_pass::system::Aggregator;
+
+pub const INTEGRITY_ATTRIBUTE: &str = "integrity";
+
+/// A SHA-384 integrity value (`sha384-…`), as used by `integrity` attributes and
+/// Content Security Policy hash sources.
+pub fn sha384(bytes: &[u8]) -> String {
+    let digest = base64::engine::general_purpose::STANDARD.encode(Sha384::digest(bytes));
+    format!("sha384-{digest}")
+}
+
+/// Adds an `integrity` attribute to a local script or stylesheet; runs once its path
+/// is resolved to the output, where every asset is already published. A file that
+/// can't be read is warned about and left without one.
+pub fn add_integrity(
+    tag: &str,
+    attrs: &mut HashMap<String, String>,
+    location: Option<&SourceLocation>,
+    env: &PostprocessEnvironment,
+    aggregator: &mut Aggregator,
+) {
+    if attrs.contains_key(INTEGRITY_ATTRIBUTE) {
+        return
+    }
+    let Some(href) = subresource(tag, attrs) else {
+        return
+    };
+    // An unresolved virtual path is already reported.
+    if crate::path_utils::is_external_url(href) || href.starts_with("@/") {
+        return
+    }
+    let path = href.split(['?', '#']).next().unwrap_or_default();
+    let file = match path.strip_prefix('/') {
+        Some(path) => env.resolver.output_dir.join(path),
+        None => env.output_file_path.parent().unwrap_or(&env.resolver.output_dir).join(path),
+    };
+    // Cleaned first: the page's own directory may not exist yet.
+    let file = path_clean::clean(file);
+    match std::fs::read(&file) {
+        Ok(bytes) => {
+            attrs.insert(String::from(INTEGRITY_ATTRIBUTE), sha384(&bytes));
+        }
+        Err(error) => {
+            let diagnostic = Diagnostic::warning(format!("no `integrity` for {href:?}: failed to read {file:?}: {error}"))
+                .with_file(&env.origin_file_path)
+                .with_location(location);
+            aggregator.diagnostics.push(diagnostic);
+        }
+    }
+}
+
+fn subresource<'a>(tag: &str, attrs: &'a HashMap<String, String>) -> Option<&'a str> {
+    match tag.to_ascii_lowercase().as_str() {
+        "script" => attrs.get("src").map(String::as_str),
+        "link" => {
+            let is_stylesheet = attrs
+                .get("rel")?
+                .split_ascii_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("stylesheet"));
+            if !is_stylesheet {
+                return None
+            }
+            attrs.get("href").map(String::as_str)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::dependency_tracking::resolve_virtual_paths::PathResolver;
+
+    fn environment(root: &Path) -> PostprocessEnvironment {
+        environment_in(root, "output/blog/post.html")
+    }
+
+    fn environment_in(root: &Path, output_file_path: &str) -> PostprocessEnvironment {
+        PostprocessEnvironment {
+            origin_file_path: root.join("pages/blog/post.html"),
+            output_file_path: root.join(output_file_path),
+            resolver: PathResolver {
+                source_input_rules: Vec::new(),
+                asset_input_rules: Vec::new(),
+                project_root: root.to_path_buf(),
+                output_dir: root.join("output"),
+            },
+            subresource_integrity: true,
+            content_security_policy: false,
+            url_attributes: Vec::new(),
+            site_url: None,
+        }
+    }
+
+    fn integrity(tag: &str, attrs: &[(&str, &str)], env: &PostprocessEnvironment) -> (Option<String>, Aggregator) {
+        let mut attrs = attrs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut aggregator = Aggregator::default();
+        add_integrity(tag, &mut attrs, None, env, &mut aggregator);
+        (attrs.remove(INTEGRITY_ATTRIBUTE), aggregator)
+    }
+
+    #[test]
+    fn hashes_with_sha384() {
+        assert_eq!(sha384(b""), "sha384-OLBgp1GsljhM2TJ+sbHjaiH9txEUvgdDTAzHv2P24donTt6/529l+9Ua0vFImLlb");
+    }
+
+    #[test]
+    fn hashes_files_relative_to_the_page_or_the_output_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = environment(dir.path());
+        std::fs::create_dir_all(dir.path().join("output/blog")).unwrap();
+        std::fs::write(dir.path().join("output/blog/post.js"), "post()").unwrap();
+        std::fs::write(dir.path().join("output/site.css"), "body {}").unwrap();
+
+        let (script, aggregator) = integrity("script", &[("src", "post.js?v=1")], &env);
+        assert_eq!(script, Some(sha384(b"post()")));
+        assert!(aggregator.diagnostics.is_empty());
+
+        let (script, aggregator) = integrity("script", &[("src", "../blog/post.js")], &environment_in(dir.path(), "output/new/page.html"));
+        assert_eq!(script, Some(sha384(b"post()")));
+        assert!(aggregator.diagnostics.is_empty());
+
+        let (link, aggregator) = integrity("link", &[("rel", "preload stylesheet"), ("href", "/site.css")], &env);
+        assert_eq!(link, Some(sha384(b"body {}")));
+        assert!(aggregator.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unreadable_files_are_warned_about() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = environment(dir.path());
+        let (script, aggregator) = integrity("script", &[("src", "/gone.js")], &env);
+        assert_eq!(script, None);
+        assert_eq!(aggregator.diagnostics.len(), 1);
+        assert!(aggregator.diagnostics[0].message.starts_with("no `integrity` for \"/gone.js\""), "{:?}", aggregator.diagnostics);
+    }
+
+    #[test]
+    fn leaves_other_elements_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = environment(dir.path());
+        let cases: &[(&str, &[(&str, &str)])] = &[
+            ("script", &[("src", "app.js"), ("integrity", "sha384-mine")]),
+            ("script", &[("src", "https://cdn.example.com/lib.js")]),
+            ("script", &[("src", "@/missing.js")]),
+            ("link", &[("rel", "icon"), ("href", "favicon.ico")]),
+            ("img", &[("src", "logo.png")]),
+        ];
+        for (tag, attrs) in cases {
+            let (value, aggregator) = integrity(tag, attrs, &env);
+            let kept = attrs.iter().find(|(key, _)| *key == INTEGRITY_ATTRIBUTE).map(|(_, value)| value.to_string());
+            assert_eq!(value, kept, "{tag} {attrs:?}");
+            assert!(aggregator.diagnostics.is_empty(), "{tag} {attrs:?}");
+        }
+    }
+}
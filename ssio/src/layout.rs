@@ -0,0 +1,196 @@
+//! Per-page layouts: which template a page is baked into.
+//!
+//! A page picks its layout with `<meta name="ssio:layout" content="…">` or a `layout`
+//! key in its front matter; otherwise it gets its input rule's template. A layout may
+//! declare a parent layout the same way, and is then baked into the parent's
+//! `<content>`. Layout paths are relative to the file declaring them, or to the project
+//! root when they start with `/`.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostics::Diagnostic;
+use crate::html::{Element, Html};
+use crate::html_pass::system::{Aggregator, State};
+
+pub const LAYOUT_META_NAME: &str = "ssio:layout";
+
+const FRONT_MATTER_DELIMITER: &str = "---";
+
+/// Splits `---` delimited front matter (`key: value` lines) off the top of a file.
+///
+/// The front matter is replaced by as many empty lines, so source locations in the
+/// rest of the file are unchanged. `None` when the file has no front matter.
+pub fn split_front_matter(source: &str) -> Option<(Vec<(String, String)>, String)> {
+    let mut lines = source.split_inclusive('\n');
+    if lines.next()?.trim_end() != FRONT_MATTER_DELIMITER {
+        return None
+    }
+    let mut entries = Vec::new();
+    let mut line_count = 1;
+    loop {
+        let line = lines.next()?;
+        line_count += 1;
+        let line = line.trim();
+        if line == FRONT_MATTER_DELIMITER {
+            break
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let (key, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        entries.push((key.trim().to_string(), value.to_string()));
+    }
+    let body = lines.collect::<String>();
+    Some((entries, format!("{}{body}", "\n".repeat(line_count))))
+}
+
+/// Turns front matter into what the rest of the build understands: `layout` becomes a
+/// `<meta name="ssio:layout">` ahead of the file's content; anything else is warned
+/// about.
+pub fn apply_front_matter(html: Html, front_matter: Vec<(String, String)>, file_path: &Path) -> State<Html> {
+    let mut aggregator = Aggregator::default();
+    let mut nodes = Vec::new();
+    for (key, value) in front_matter {
+        match key.as_str() {
+            "layout" => {
+                let attrs = HashMap::from([
+                    (String::from("name"), String::from(LAYOUT_META_NAME)),
+                    (String::from("content"), value),
+                ]);
+                nodes.push(Html::Element(Element { tag: String::from("meta"), attrs, children: Vec::default(), location: None }));
+            }
+            _ => {
+                let diagnostic = Diagnostic::warning(format!("unknown front matter key: `{key}`"))
+                    .with_file(file_path);
+                aggregator.diagnostics.push(diagnostic);
+            }
+        }
+    }
+    if nodes.is_empty() {
+        return aggregator.wrap(html)
+    }
+    nodes.push(html);
+    aggregator.wrap(Html::Fragment(nodes))
+}
+
+/// Resolves a declared layout against the file that declared it.
+pub fn resolve_layout(declared_in: &Path, layout: &str, project_root: &Path) -> PathBuf {
+    let path = match layout.strip_prefix('/') {
+        Some(layout) => project_root.join(layout),
+        None => declared_in.parent().unwrap_or(project_root).join(layout),
+    };
+    path_clean::clean(path)
+}
+
+impl Html {
+    /// Removes every `<meta name="ssio:layout">`, returning the first one's `content`.
+    pub fn take_layout(&mut self) -> Option<String> {
+        match self {
+            Self::Element(element) => element.take_layout(),
+            Self::Fragment(nodes) => take_layout(nodes),
+            Self::Text(_) => None,
+        }
+    }
+}
+
+impl Element {
+    fn take_layout(&mut self) -> Option<String> {
+        take_layout(&mut self.children)
+    }
+    fn is_layout_meta(&self) -> bool {
+        self.tag.eq_ignore_ascii_case("meta")
+            && self.attrs.get("name").is_some_and(|name| name == LAYOUT_META_NAME)
+    }
+}
+
+fn take_layout(nodes: &mut Vec<Html>) -> Option<String> {
+    let mut layout = None;
+    nodes.retain_mut(|node| {
+        match node {
+            Html::Element(element) if element.is_layout_meta() => {
+                let content = element.attrs.get("content").cloned();
+                layout = layout.take().or(content);
+                false
+            }
+            node => {
+                let nested = node.take_layout();
+                layout = layout.take().or(nested);
+                true
+            }
+        }
+    });
+    layout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::{Compiler, InputRule};
+    use crate::html::ParserMode;
+
+    #[test]
+    fn splits_front_matter_keeping_line_numbers() {
+        let source = "---\nlayout: \"post.html\"\n# a comment\n\ntitle: Hello: world\n---\n<p>body</p>\n";
+        let (entries, body) = split_front_matter(source).unwrap();
+        assert_eq!(entries, [
+            (String::from("layout"), String::from("post.html")),
+            (String::from("title"), String::from("Hello: world")),
+        ]);
+        assert_eq!(body, "\n\n\n\n\n\n<p>body</p>\n");
+    }
+
+    #[test]
+    fn files_without_closed_front_matter_are_left_alone() {
+        assert!(split_front_matter("<p>body</p>\n---\n").is_none());
+        assert!(split_front_matter("---\nlayout: post.html\n<p>body</p>\n").is_none());
+    }
+
+    #[test]
+    fn front_matter_layout_becomes_a_meta() {
+        let front_matter = vec![
+            (String::from("layout"), String::from("post.html")),
+            (String::from("title"), String::from("Hello")),
+        ];
+        let html = Html::parse("<p>body</p>", ParserMode::fragment("div"), Default::default());
+        let mut state = apply_front_matter(html, front_matter, Path::new("/site/index.html"));
+        assert_eq!(state.value.take_layout().as_deref(), Some("post.html"));
+        assert_eq!(state.value.take_layout(), None);
+        let messages = state.aggregator.diagnostics.iter().map(|x| x.message.as_str()).collect::<Vec<_>>();
+        assert_eq!(messages, ["unknown front matter key: `title`"]);
+    }
+
+    #[test]
+    fn takes_the_first_layout_meta() {
+        let source = r#"<div><meta name="ssio:layout" content="a.html"></div><meta name="ssio:layout" content="b.html"><p>x</p>"#;
+        let mut html = Html::parse(source, ParserMode::fragment("div"), Default::default());
+        assert_eq!(html.take_layout().as_deref(), Some("a.html"));
+        assert_eq!(html.take_layout(), None);
+    }
+
+    #[test]
+    fn resolves_layouts_against_the_declaring_file_or_the_root() {
+        let root = Path::new("/site");
+        let page = Path::new("/site/posts/hello.html");
+        assert_eq!(resolve_layout(page, "../layouts/post.html", root), Path::new("/site/layouts/post.html"));
+        assert_eq!(resolve_layout(page, "/layouts/post.html", root), Path::new("/site/layouts/post.html"));
+    }
+
+    #[test]
+    fn layout_cycles_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("index.html"), "---\nlayout: a.html\n---\n<p>page</p>").unwrap();
+        std::fs::write(root.join("a.html"), "---\nlayout: b.html\n---\n<html><body><content></content></body></html>").unwrap();
+        std::fs::write(root.join("b.html"), "---\nlayout: a.html\n---\n<html><body><content></content></body></html>").unwrap();
+        let mut compiler = Compiler::new(root, root.join("output"));
+        compiler.input_paths = vec![InputRule::new(root.join("index.html"))];
+        let error = compiler.run().unwrap_err().to_string();
+        assert!(error.starts_with("layout cycle in page"), "{error}");
+        assert!(!root.join("output/index.html").exists());
+    }
+}
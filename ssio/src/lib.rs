@@ -0,0 +1,55 @@
+//! Static site compiler with path-aware HTML includes and templates.
+//!
+//! Build a [`Compiler`] programmatically or from a [`ProjectManifest`], run it, and
+//! inspect the returned [`BuildReport`]:
+//!
+//! ```no_run
+//! let manifest_path = std::path::Path::new("site/site.toml");
+//! let manifest = ssio::load_project_manifest(manifest_path).unwrap();
+//! let compiler = manifest.compiler(manifest_path.parent().unwrap());
+//! let report = compiler.run().unwrap();
+//! for page in report.pages.iter() {
+//!     println!("{:?} → {:?}", page.source, page.output);
+//! }
+//! report.emit_diagnostics();
+//! ```
+#[macro_use] extern crate markup5ever;
+
+mod html;
+mod html_parser;
+mod html_parser2;
+mod html_string;
+mod pretty_html;
+#[doc(hidden)]
+pub mod cli;
+pub mod compile;
+mod template;
+mod layout;
+mod head;
+mod images;
+mod inline_assets;
+mod integrity;
+mod csp;
+mod modules;
+pub mod manifest;
+mod symlink;
+mod html_pass;
+mod path_utils;
+mod dependency_tracking;
+mod css_process;
+pub mod diagnostics;
+mod parser_check;
+mod build_cache;
+mod prune;
+mod scaffold;
+mod graph;
+mod audit;
+
+pub use compile::{AssetReport, BuildPlan, BuildReport, BundleRule, Compiler, InputRule, OutputKind, PagePlan, PageReport, PlannedOutput};
+pub use csp::{ContentSecurityPolicy, CspOutput};
+pub use dependency_tracking::data::{UrlAttribute, UrlKind};
+pub use diagnostics::{Diagnostic, Severity, SourceLocation, SourcePosition};
+pub use html::ParserBackend;
+pub use html_pass::system::Dependency;
+pub use manifest::{load_project_manifest, BuildOptions, ProjectManifest};
+pub use modules::ModuleBundling;
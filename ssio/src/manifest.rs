@@ -1,10 +1,15 @@
 use serde::Deserialize;
 use std::path::{Path, PathBuf};
 
-use crate::compile::Compiler;
+use std::collections::HashMap;
+
+use crate::compile::{BuildPlan, BuildReport, Compiler};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::html::ParserBackend;
 
 /// The full config file
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ProjectManifest {
     #[serde(default = "default_root")]
     pub root: PathBuf,
@@ -16,6 +21,10 @@ pub struct ProjectManifest {
     #[serde(default)]
     pub pretty_print: Option<bool>,
 
+    /// Which HTML parser backend to use.
+    #[serde(default)]
+    pub parser: ParserBackend,
+
     #[serde(default)]
     pub globs: Vec<GlobRewriteRule>, 
 
@@ -27,6 +36,48 @@ pub struct ProjectManifest {
 
     #[serde(default)]
     pub bundles: Vec<BundleRule>,
+
+    /// Page outputs (globs relative to `output_dir`) reached from outside the site,
+    /// which aren't reported as orphans when nothing links to them.
+    #[serde(default = "default_entry_points")]
+    pub entry_points: Vec<String>,
+
+    /// Images and fonts up to this many bytes, referenced by `<img src>` or a style's
+    /// `url()`, are embedded as data URIs instead of linked.
+    #[serde(default)]
+    pub inline_threshold: Option<u64>,
+
+    /// Add `integrity` attributes (SHA-384) to local scripts and stylesheets.
+    #[serde(default)]
+    pub subresource_integrity: bool,
+
+    /// A Content Security Policy for every page, allowing its inline scripts and
+    /// styles by hash. Pages then aren't pretty printed by default, and
+    /// `pretty_print = true` is an error: reformatted scripts and styles would no
+    /// longer match their hashes.
+    #[serde(default)]
+    pub csp: Option<crate::csp::ContentSecurityPolicy>,
+
+    /// Bundle local `<script type="module">`s with their imports, and minify them with
+    /// `minify = true`.
+    #[serde(default)]
+    pub modules: Option<crate::modules::ModuleBundling>,
+
+    /// Extra `(tag, attribute, kind)` entries for attributes holding URLs, such as
+    /// those of custom elements; `kind` is `asset` (the default), `link`, `srcset` or
+    /// `style` or `absolute`.
+    #[serde(default)]
+    pub url_attributes: Vec<crate::dependency_tracking::data::UrlAttribute>,
+
+    /// Where `output_dir` is published, such as `https://example.com/`. Open Graph and
+    /// Twitter card URLs (`og:image`, …) are made absolute from it; without one
+    /// they're left as written.
+    #[serde(default)]
+    pub site_url: Option<String>,
+
+    /// The file this manifest was loaded from, for diagnostics.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 fn default_root() -> PathBuf {
@@ -37,12 +88,33 @@ fn default_output() -> PathBuf {
     PathBuf::from("output")
 }
 
-fn default_pretty_print() -> bool {
-    true
+fn default_entry_points() -> Vec<String> {
+    vec![String::from("index.html")]
+}
+
+/// Where the incremental build database lives, relative to the project root.
+pub const CACHE_DIR_NAME: &str = ".ssio-cache";
+
+/// Command line overrides for a manifest build.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct BuildOptions {
+    /// Applies when the manifest doesn't set `pretty_print`.
+    pub pretty_print: Option<bool>,
+    /// Overrides the manifest's `parser`.
+    pub parser: Option<ParserBackend>,
+    /// Compile every page without reading or writing the build cache; stale outputs
+    /// are still pruned.
+    pub no_cache: bool,
+    /// Wipe the output directory before building.
+    pub clean: bool,
+    /// Plan the build without writing anything; overrides `clean`.
+    pub dry_run: bool,
 }
 
 /// Glob-based rewrite rules
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct GlobRewriteRule {
     /// Glob pattern to match files, relative to project root
     pub pattern: String,
@@ -50,10 +122,16 @@ pub struct GlobRewriteRule {
     /// Prefix to strip from the matched path
     #[serde(default)]
     pub strip_prefix: Option<String>,
+
+    /// Template for the matched pages instead of the top-level `template`, relative
+    /// to project root
+    #[serde(default)]
+    pub template: Option<PathBuf>,
 }
 
 /// Manual rewrite rules for specific files
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ManualRewriteRule {
     /// Input file path
     pub source: PathBuf,
@@ -64,7 +142,7 @@ pub struct ManualRewriteRule {
 
 /// Static assets to copy into output directory
 #[derive(Debug, Deserialize)]
-#[serde(untagged)]
+#[serde(untagged, deny_unknown_fields)]
 pub enum AssetRule {
     Glob {
         /// Glob pattern to match asset files
@@ -78,6 +156,7 @@ pub enum AssetRule {
 
 /// Static assets to copy into output directory
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BundleRule {
     /// Glob pattern to match asset files
     pub location: PathBuf,
@@ -86,52 +165,393 @@ pub struct BundleRule {
 
 pub fn load_project_manifest(path: impl AsRef<Path>) -> Result<ProjectManifest, Box<dyn std::error::Error>> {
     let path = path.as_ref();
-    let text = std::fs::read_to_string(path)?;
-    let config: ProjectManifest = toml::from_str(&text)?;
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| format!("failed to read {}: {error}", path.display()))?;
+    let mut config: ProjectManifest = toml::from_str(&text)
+        .map_err(|error| format!("invalid manifest {}: {}", path.display(), error.to_string().trim_end()))?;
+    config.path = Some(path.to_path_buf());
     Ok(config)
 }
 
 impl ProjectManifest {
-    pub fn execute(&self, manifest_dir: impl AsRef<Path>, pretty_print: Option<bool>) {
-        let manifest_dir = manifest_dir.as_ref();
-        let working_dir = manifest_dir.join(&self.root);
-        std::env::set_current_dir(&working_dir).unwrap();
+    /// The project root: `root` resolved against the manifest's directory, made absolute.
+    pub fn project_root(&self, manifest_dir: impl AsRef<Path>) -> PathBuf {
+        let root = manifest_dir.as_ref().join(&self.root);
+        let root = std::path::absolute(&root).unwrap_or(root);
+        path_clean::clean(root)
+    }
+    /// Builds the [`Compiler`] described by this manifest.
+    ///
+    /// `root` is resolved against `manifest_dir`; glob patterns, the template, bundle
+    /// locations and the output directory are resolved against the project root.
+    pub fn compiler(&self, manifest_dir: impl AsRef<Path>) -> Compiler {
+        let project_root = self.project_root(manifest_dir);
         let bundles = self.bundles
             .iter()
             .map(|bundle| {
                 crate::compile::BundleRule {
-                    location: bundle.location.clone(),
+                    location: project_root.join(&bundle.location),
                 }
             })
             .collect::<Vec<_>>();
-        let inputs = self.globs
+        let inputs = self
+            .glob_matches(&project_root)
+            .into_iter()
+            .map(|(index, path)| {
+                // A `strip_prefix` that doesn't match is reported by `validate`.
+                let target = self.globs[index].strip_prefix
+                    .as_ref()
+                    .and_then(|x| path.strip_prefix(project_root.join(x)).ok())
+                    .map(Path::to_path_buf);
+                let mut rule = crate::compile::InputRule::new(path);
+                rule.target = target;
+                rule.template = self.globs[index].template.as_ref().map(|template| project_root.join(template));
+                rule
+            })
+            .collect::<Vec<_>>();
+        let mut compiler = Compiler::new(&project_root, project_root.join(&self.output_dir));
+        compiler.input_paths = inputs;
+        compiler.template_path = self.template.as_ref().map(|template| project_root.join(template));
+        compiler.pretty_print = self.pretty_print.unwrap_or(self.csp.is_none());
+        compiler.bundles = bundles;
+        compiler.parser_backend = self.parser;
+        compiler.cache_dir = Some(project_root.join(CACHE_DIR_NAME));
+        compiler.inline_threshold = self.inline_threshold;
+        compiler.subresource_integrity = self.subresource_integrity;
+        compiler.csp = self.csp.clone();
+        compiler.modules = self.modules.clone();
+        compiler.url_attributes = self.url_attributes.clone();
+        compiler.site_url = self.site_url.clone();
+        compiler
+    }
+    /// Every file matched by `globs`, with the index of the rule that matched it.
+    fn glob_matches(&self, project_root: &Path) -> Vec<(usize, PathBuf)> {
+        self.globs
             .iter()
-            .flat_map(|rule| {
-                crate::path_utils::resolve_file_path_paterns(&[rule.pattern.clone()])
+            .enumerate()
+            .flat_map(|(index, rule)| {
+                crate::path_utils::resolve_file_path_paterns(&[glob_pattern_under(project_root, &rule.pattern)])
                     .into_iter()
-                    .flat_map(|x| x)
-                    .map(|path| {
-                        let target = rule.strip_prefix
-                            .as_ref()
-                            .map(|x| {
-                                path.strip_prefix(x).unwrap().to_path_buf()
-                            });
-                        crate::compile::InputRule {
-                            source: path,
-                            target,
-                        }
-                    })
+                    .flatten()
+                    .map(move |path| (index, path))
             })
-            .collect::<Vec<_>>();
-        let compiler = Compiler {
-            project_root: working_dir,
-            input_paths: inputs,
-            template_path: self.template.clone(),
-            output_dir: self.output_dir.clone(),
-            pretty_print: self.pretty_print.unwrap_or(pretty_print.unwrap_or(true)),
-            bundles,
+            .collect()
+    }
+    /// Checks the manifest against the file system; every problem names the
+    /// offending key.
+    pub fn validate(&self, manifest_dir: impl AsRef<Path>) -> Vec<Diagnostic> {
+        let project_root = self.project_root(manifest_dir);
+        let mut diagnostics = Vec::new();
+        let mut report = |diagnostic: Diagnostic| {
+            diagnostics.push(match self.path.as_ref() {
+                Some(path) => diagnostic.with_file(path),
+                None => diagnostic,
+            });
         };
-        compiler.run();
+        if !project_root.is_dir() {
+            report(Diagnostic::error(format!("`root`: directory not found: {project_root:?}")));
+        }
+        if let Some(template) = self.template.as_ref() {
+            let path = project_root.join(template);
+            if !path.is_file() {
+                report(Diagnostic::error(format!("`template`: file not found: {path:?}")));
+            }
+        }
+        for (index, bundle) in self.bundles.iter().enumerate() {
+            let path = project_root.join(&bundle.location);
+            if !path.exists() {
+                report(Diagnostic::error(format!("`bundles[{index}].location`: not found: {path:?}")));
+            }
+        }
+        let matches = self.glob_matches(&project_root);
+        for (index, rule) in self.globs.iter().enumerate() {
+            if let Err(error) = glob::Pattern::new(&rule.pattern) {
+                report(Diagnostic::error(format!("`globs[{index}].pattern`: invalid glob {:?}: {error}", rule.pattern)));
+                continue;
+            }
+            let rule_matches = matches
+                .iter()
+                .filter(|(rule_index, _)| *rule_index == index)
+                .map(|(_, path)| path)
+                .collect::<Vec<_>>();
+            if let Some(template) = rule.template.as_ref() {
+                let path = project_root.join(template);
+                if !path.is_file() {
+                    report(Diagnostic::error(format!("`globs[{index}].template`: file not found: {path:?}")));
+                }
+            }
+            if rule_matches.is_empty() {
+                report(Diagnostic::error(format!("`globs[{index}].pattern`: {:?} matches no files", rule.pattern)));
+            }
+            if let Some(strip_prefix) = rule.strip_prefix.as_ref() {
+                let prefix = project_root.join(strip_prefix);
+                let unprefixed = rule_matches
+                    .iter()
+                    .filter(|path| !path.starts_with(&prefix))
+                    .collect::<Vec<_>>();
+                if let Some(path) = unprefixed.first() {
+                    report(Diagnostic::error(format!(
+                        "`globs[{index}].strip_prefix`: {strip_prefix:?} is not a prefix of {path:?} (and {} other matches)",
+                        unprefixed.len() - 1,
+                    )));
+                }
+            }
+        }
+        if self.pretty_print == Some(true) && self.csp.is_some() {
+            report(Diagnostic::error("`pretty_print`: pretty printing would break the `csp` hashes of inline scripts and styles"));
+        }
+        for (index, pattern) in self.entry_points.iter().enumerate() {
+            if let Err(error) = glob::Pattern::new(pattern) {
+                report(Diagnostic::error(format!("`entry_points[{index}]`: invalid glob {pattern:?}: {error}")));
+            }
+        }
+        let output_dir = project_root.join(&self.output_dir);
+        let mut outputs = HashMap::<PathBuf, (usize, &PathBuf)>::new();
+        for (index, path) in matches.iter() {
+            let target = self.globs[*index].strip_prefix
+                .as_ref()
+                .and_then(|x| path.strip_prefix(project_root.join(x)).ok())
+                .or_else(|| path.strip_prefix(&project_root).ok());
+            let Some(target) = target else { continue };
+            let output = path_clean::clean(output_dir.join(target));
+            match outputs.get(&output) {
+                Some((other_index, other_path)) if *other_path != path => {
+                    report(Diagnostic::error(format!(
+                        "`globs[{index}]`: {path:?} and {other_path:?} (from `globs[{other_index}]`) both map to {output:?}",
+                    )));
+                }
+                Some((other_index, _)) => {
+                    report(Diagnostic::error(format!(
+                        "`globs[{index}]`: {path:?} is matched by `globs[{other_index}]` too",
+                    )));
+                }
+                None => {
+                    outputs.insert(output, (*index, path));
+                }
+            }
+        }
+        diagnostics
+    }
+    /// Validates the manifest, then runs the build with the given command line
+    /// overrides. On validation errors nothing is built; the report holds only the
+    /// diagnostics.
+    pub fn execute(
+        &self,
+        manifest_dir: impl AsRef<Path>,
+        options: &BuildOptions,
+    ) -> Result<BuildReport, Box<dyn std::error::Error>> {
+        let manifest_dir = manifest_dir.as_ref();
+        let diagnostics = self.validate(manifest_dir);
+        if diagnostics.iter().any(|x| x.severity == Severity::Error) {
+            return Ok(BuildReport { diagnostics, ..Default::default() })
+        }
+        let mut compiler = self.compiler(manifest_dir);
+        compiler.pretty_print = self.pretty_print.or(options.pretty_print).unwrap_or(self.csp.is_none());
+        compiler.parser_backend = options.parser.unwrap_or(self.parser);
+        compiler.incremental = !options.no_cache;
+        compiler.dry_run = options.dry_run;
+        if options.clean && !options.dry_run {
+            crate::prune::clean_output_dir(&compiler.output_dir, &compiler.project_root)?;
+        }
+        let mut report = compiler.run()?;
+        report.diagnostics.splice(0..0, diagnostics);
+        if !report.diagnostics.iter().any(|x| x.severity == Severity::Error) {
+            let audit = self.audit(&compiler.project_root, &report.plan);
+            report.diagnostics.extend(audit);
+        }
+        Ok(report)
+    }
+    /// Warnings for orphan pages and for bundle and asset files nothing references.
+    fn audit(&self, project_root: &Path, plan: &BuildPlan) -> Vec<Diagnostic> {
+        // Invalid entry points are reported by `validate`.
+        let entry_points = self.entry_points
+            .iter()
+            .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+            .collect::<Vec<_>>();
+        let bundle_files = self.bundles
+            .iter()
+            .flat_map(|bundle| crate::prune::walk(&project_root.join(&bundle.location)));
+        let asset_files = self.assets
+            .iter()
+            .flat_map(|AssetRule::Glob { pattern, .. }| {
+                crate::path_utils::resolve_file_path_paterns(&[glob_pattern_under(project_root, pattern)])
+                    .into_iter()
+                    .flatten()
+            });
+        let mut diagnostics = crate::audit::orphan_pages(plan, &entry_points);
+        diagnostics.extend(crate::audit::unused_assets(plan, bundle_files.chain(asset_files)));
+        diagnostics
     }
 }
 
+/// Anchors a manifest glob at the project root, escaping the root so its own
+/// characters are never treated as glob syntax.
+fn glob_pattern_under(project_root: &Path, pattern: &str) -> String {
+    let root = glob::Pattern::escape(&project_root.to_string_lossy());
+    format!("{}/{}", root.trim_end_matches('/'), pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    /// A project with `pages/index.html` and `posts/index.html`, validated against the
+    /// given manifest; returns the error messages.
+    fn validation_errors(manifest: &str) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("pages/index.html"), "<p>home</p>");
+        write(&dir.path().join("posts/index.html"), "<p>posts</p>");
+        let manifest = toml::from_str::<ProjectManifest>(manifest).unwrap();
+        manifest
+            .validate(dir.path())
+            .into_iter()
+            .map(|diagnostic| {
+                assert_eq!(diagnostic.severity, Severity::Error);
+                diagnostic.message
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_manifest_has_no_errors() {
+        let errors = validation_errors(r#"
+            [[globs]]
+            pattern = "pages/**/*.html"
+            strip_prefix = "pages/"
+        "#);
+        assert_eq!(errors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn missing_files_are_reported_by_key() {
+        let errors = validation_errors(r#"
+            template = "base.html"
+            [[globs]]
+            pattern = "pages/*.html"
+            template = "post.html"
+            [[bundles]]
+            location = "static"
+        "#);
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors[0].starts_with("`template`: file not found"));
+        assert!(errors[1].starts_with("`bundles[0].location`: not found"));
+        assert!(errors[2].starts_with("`globs[0].template`: file not found"));
+    }
+
+    #[test]
+    fn bad_globs_are_reported_by_key() {
+        let errors = validation_errors(r#"
+            entry_points = ["[oops"]
+            [[globs]]
+            pattern = "drafts/*.html"
+            [[globs]]
+            pattern = "[oops"
+            [[globs]]
+            pattern = "pages/*.html"
+            strip_prefix = "posts/"
+        "#);
+        assert_eq!(errors.len(), 4, "{errors:?}");
+        assert!(errors[0].starts_with("`globs[0].pattern`: \"drafts/*.html\" matches no files"));
+        assert!(errors[1].starts_with("`globs[1].pattern`: invalid glob"));
+        assert!(errors[2].starts_with("`globs[2].strip_prefix`: \"posts/\" is not a prefix"));
+        assert!(errors[3].starts_with("`entry_points[0]`: invalid glob"));
+    }
+
+    #[test]
+    fn colliding_outputs_are_reported() {
+        let errors = validation_errors(r#"
+            [[globs]]
+            pattern = "pages/*.html"
+            strip_prefix = "pages/"
+            [[globs]]
+            pattern = "posts/*.html"
+            strip_prefix = "posts/"
+        "#);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].starts_with("`globs[1]`:"));
+        assert!(errors[0].contains("both map to"));
+    }
+
+    #[test]
+    fn pages_matched_by_two_globs_are_reported() {
+        let errors = validation_errors(r#"
+            [[globs]]
+            pattern = "pages/*.html"
+            [[globs]]
+            pattern = "pages/index.html"
+            template = "pages/index.html"
+        "#);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].starts_with("`globs[1]`:"));
+        assert!(errors[0].ends_with("pages/index.html\" is matched by `globs[0]` too"), "{errors:?}");
+    }
+
+    #[test]
+    fn a_dry_run_only_plans() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(&root.join("base.html"), "<html><head><link rel=\"stylesheet\" href=\"site.css\"></head><body><include src=\"nav.html\"></include><content></content></body></html>");
+        write(&root.join("nav.html"), "<nav>nav</nav>");
+        write(&root.join("site.css"), "body { margin: 0 }");
+        write(&root.join("pages/index.html"), "<p>home</p>");
+        let manifest = toml::from_str::<ProjectManifest>(r#"
+            template = "base.html"
+            [[globs]]
+            pattern = "pages/*.html"
+            strip_prefix = "pages/"
+        "#).unwrap();
+        let options = BuildOptions { dry_run: true, clean: true, ..Default::default() };
+        let report = manifest.execute(root, &options).unwrap();
+        assert!(!report.has_errors(), "{:?}", report.diagnostics);
+        assert!(!root.join("output").exists());
+        assert!(!root.join(CACHE_DIR_NAME).exists());
+        assert!(report.pages.is_empty() && report.assets.is_empty());
+        let plan = serde_json::to_value(&report.plan).unwrap();
+        assert_eq!(plan, serde_json::json!({
+            "project_root": root,
+            "output_dir": root.join("output"),
+            "template": root.join("base.html"),
+            "pages": [{
+                "source": root.join("pages/index.html"),
+                "output": root.join("output/index.html"),
+                "includes": [root.join("nav.html")],
+                "layouts": [root.join("base.html")],
+                "cached": false,
+            }],
+            "assets": [{
+                "source": root.join("site.css"),
+                "output": root.join("output/site.css"),
+                "kind": "asset",
+            }],
+            "stale": [],
+        }));
+    }
+
+    #[test]
+    fn pretty_printing_is_off_by_default_with_a_csp() {
+        let globs = "[[globs]]\npattern = \"pages/*.html\"\n";
+        let errors = validation_errors(&format!("pretty_print = true\n[csp]\n{globs}"));
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].starts_with("`pretty_print`:"));
+        let pretty_print = |manifest: &str| {
+            toml::from_str::<ProjectManifest>(manifest).unwrap().compiler(".").pretty_print
+        };
+        assert!(pretty_print(globs));
+        assert!(!pretty_print(&format!("[csp]\n{globs}")));
+        assert!(!pretty_print(&format!("pretty_print = false\n{globs}")));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("site.toml");
+        write(&path, "outptu_dir = \"public\"\n");
+        let error = load_project_manifest(&path).unwrap_err().to_string();
+        assert!(error.contains("unknown field `outptu_dir`"), "{error}");
+    }
+}
@@ -0,0 +1,288 @@
+//! JavaScript module bundling for `<script type="module" src>`.
+//!
+//! When the build bundles modules (see [`crate::compile::Compiler::modules`]), every
+//! local module script is an entry: it's bundled with everything it imports into one
+//! file, optionally minified, and written to `output_dir/_modules` under its path
+//! relative to the project root, with a hash of its code in the name. The page's
+//! `<script src>` then points at the bundle (see
+//! [`crate::dependency_tracking::resolve_virtual_paths::PathResolver`]), and modules
+//! only reached through imports aren't published.
+//!
+//! Imports must be relative (`./`, `../`) or start at the project root (`/`); an entry
+//! with a bare import (`from "lit"`) or a syntax error is warned about and published
+//! as written instead.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use swc_bundler::{Bundle, BundleKind, Bundler, Hook, Load, ModuleData, ModuleRecord};
+use swc_common::{FileName, GLOBALS, Globals, Mark, SourceMap, Span, Spanned, sync::Lrc};
+use swc_ecma_ast::{
+    EsVersion, Expr, IdentName, KeyValueProp, MemberExpr, MemberProp, MetaPropExpr, MetaPropKind, Module, Program,
+    PropName,
+};
+use swc_ecma_codegen::{Emitter, text_writer::JsWriter};
+use swc_ecma_loader::resolve::{Resolution, Resolve};
+use swc_ecma_minifier::option::{CompressOptions, ExtraOptions, MangleOptions, MinifyOptions};
+use swc_ecma_parser::{EsSyntax, Syntax};
+
+use crate::html_pass::system::{Aggregator, Dependency, Scope};
+
+/// Where bundles are written, relative to the output directory.
+pub const MODULE_OUTPUT_DIR: &str = "_modules";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModuleBundling {
+    /// Minify each bundle (compress and mangle).
+    #[serde(default)]
+    pub minify: bool,
+}
+
+/// An entry bundled with its imports.
+#[derive(Debug, Clone)]
+pub struct BundledModule {
+    pub code: String,
+    /// Every file the bundle was built from, the entry included.
+    pub inputs: Vec<PathBuf>,
+}
+
+/// Records the source of a local `<script type="module" src>` as an entry.
+pub fn register_module_script(attrs: &HashMap<String, String>, scope: &Scope, aggregator: &mut Aggregator) {
+    let is_module = attrs
+        .get("type")
+        .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("module"));
+    let Some(src) = attrs.get("src").filter(|src| is_module && !crate::path_utils::is_external_url(src)) else {
+        return
+    };
+    aggregator.module_scripts.insert(Dependency {
+        origin: path_clean::clean(&scope.source_path),
+        target: path_clean::clean(PathBuf::from(src)),
+        is_internal: Some(false),
+    });
+}
+
+/// Bundles an entry module, or explains why it couldn't be.
+pub fn bundle(entry: &Path, project_root: &Path, options: &ModuleBundling) -> Result<BundledModule, String> {
+    let globals = Globals::default();
+    let cm = Lrc::new(SourceMap::default());
+    let loader = Loader { cm: cm.clone(), loaded: Mutex::default() };
+    let resolver = Resolver { project_root: project_root.to_path_buf() };
+    let config = swc_bundler::Config {
+        // Minification renames on its own.
+        disable_hygiene: options.minify,
+        disable_fixer: options.minify,
+        ..Default::default()
+    };
+    let mut bundler = Bundler::new(&globals, cm.clone(), &loader, resolver, config, Box::new(ImportMetaHook));
+    let entries = HashMap::from([(String::from("main"), FileName::Real(entry.to_path_buf()))]);
+    let bundles = bundler
+        .bundle(entries)
+        .map_err(|error| format!("failed to bundle {entry:?}: {}", error.root_cause()))?;
+    let module = bundles
+        .into_iter()
+        .find_map(|Bundle { kind, module, .. }| matches!(kind, BundleKind::Named { .. }).then_some(module))
+        .ok_or_else(|| format!("failed to bundle {entry:?}: no output"))?;
+    let module = match options.minify {
+        true => GLOBALS.set(&globals, || minify(module, cm.clone())),
+        false => module,
+    };
+    let code = emit(&module, cm, options.minify)
+        .map_err(|error| format!("failed to write the bundle of {entry:?}: {error}"))?;
+    let mut inputs = loader.loaded.into_inner().unwrap();
+    inputs.sort();
+    inputs.dedup();
+    Ok(BundledModule { code, inputs })
+}
+
+/// The bundle's output path: the entry's path under [`MODULE_OUTPUT_DIR`], with a hash
+/// of the code before the extension.
+pub fn output_path(entry: &Path, project_root: &Path, output_dir: &Path, code: &str) -> PathBuf {
+    let relative = entry.strip_prefix(project_root).unwrap_or(entry);
+    let stem = relative.file_stem().unwrap_or_default().to_string_lossy();
+    let digest = crate::build_cache::hex_digest(code.as_bytes());
+    let file_name = format!("{stem}.{}.js", &digest[..8]);
+    output_dir.join(MODULE_OUTPUT_DIR).join(relative.with_file_name(file_name))
+}
+
+fn minify(module: Module, cm: Lrc<SourceMap>) -> Module {
+    let unresolved_mark = Mark::new();
+    let top_level_mark = Mark::new();
+    let program = Program::Module(module)
+        .apply(swc_ecma_transforms_base::resolver(unresolved_mark, top_level_mark, false));
+    #[allow(deprecated)]
+    let mangle = MangleOptions {
+        props: None,
+        top_level: Some(true),
+        keep_class_names: false,
+        keep_fn_names: false,
+        keep_private_props: false,
+        ie8: false,
+        safari10: false,
+        reserved: Vec::default(),
+        eval: false,
+        disable_char_freq: false,
+    };
+    let options = MinifyOptions {
+        compress: Some(CompressOptions { module: true, ..Default::default() }),
+        mangle: Some(mangle),
+        ..Default::default()
+    };
+    let extra = ExtraOptions { unresolved_mark, top_level_mark, mangle_name_cache: None };
+    let program = swc_ecma_minifier::optimize(program, cm, None, None, &options, &extra)
+        .apply(swc_ecma_transforms_base::fixer::fixer(None));
+    match program {
+        Program::Module(module) => module,
+        Program::Script(_) => unreachable!("minifying a module returns a module"),
+    }
+}
+
+fn emit(module: &Module, cm: Lrc<SourceMap>, minify: bool) -> std::io::Result<String> {
+    let mut code = Vec::new();
+    let mut emitter = Emitter {
+        cfg: swc_ecma_codegen::Config::default().with_minify(minify),
+        cm: cm.clone(),
+        comments: None,
+        wr: JsWriter::new(cm, "\n", &mut code, None),
+    };
+    emitter.emit_module(module)?;
+    Ok(String::from_utf8_lossy(&code).into_owned())
+}
+
+// ————————————————————————————————————————————————————————————————————————————
+// BUNDLER HOOKS
+// ————————————————————————————————————————————————————————————————————————————
+
+/// Parses modules from disk, recording which were loaded.
+struct Loader {
+    cm: Lrc<SourceMap>,
+    loaded: Mutex<Vec<PathBuf>>,
+}
+
+impl Load for Loader {
+    fn load(&self, file: &FileName) -> Result<ModuleData, anyhow::Error> {
+        let FileName::Real(path) = file else {
+            anyhow::bail!("unsupported module {file}")
+        };
+        self.loaded.lock().unwrap().push(path.clone());
+        let fm = self.cm
+            .load_file(path)
+            .map_err(|error| anyhow::anyhow!("failed to read {path:?}: {error}"))?;
+        let syntax = Syntax::Es(EsSyntax::default());
+        let module = swc_ecma_parser::parse_file_as_module(&fm, syntax, EsVersion::latest(), None, &mut Vec::new())
+            .map_err(|error| {
+                let position = self.cm.lookup_char_pos(error.span().lo);
+                anyhow::anyhow!("{}:{}:{}: {}", path.display(), position.line, position.col_display + 1, error.kind().msg())
+            })?;
+        Ok(ModuleData { fm, module, helpers: Default::default() })
+    }
+}
+
+/// Resolves relative and project root imports to files.
+struct Resolver {
+    project_root: PathBuf,
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, base: &FileName, specifier: &str) -> Result<Resolution, anyhow::Error> {
+        let FileName::Real(base) = base else {
+            anyhow::bail!("unsupported module {base}")
+        };
+        let path = if let Some(path) = specifier.strip_prefix('/') {
+            self.project_root.join(path)
+        } else if specifier.starts_with("./") || specifier.starts_with("../") {
+            base.parent().unwrap_or(&self.project_root).join(specifier)
+        } else {
+            anyhow::bail!("bare import {specifier:?} in {base:?}; only relative and `/` imports are bundled")
+        };
+        Ok(Resolution { filename: FileName::Real(path_clean::clean(path)), slug: None })
+    }
+}
+
+/// `import.meta` of a bundled module is the bundle's own: its `url` is the bundle's.
+struct ImportMetaHook;
+
+impl Hook for ImportMetaHook {
+    fn get_import_meta_props(&self, span: Span, _: &ModuleRecord) -> Result<Vec<KeyValueProp>, anyhow::Error> {
+        let import_meta = Expr::MetaProp(MetaPropExpr { span, kind: MetaPropKind::ImportMeta });
+        let url = Expr::Member(MemberExpr {
+            span,
+            obj: Box::new(import_meta),
+            prop: MemberProp::Ident(IdentName::new("url".into(), span)),
+        });
+        Ok(vec![KeyValueProp { key: PropName::Ident(IdentName::new("url".into(), span)), value: Box::new(url) }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn relative_and_root_imports_are_bundled() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "js/app.js", "import { greet } from './greet.js';\nimport { shout } from '/lib/shout.js';\nshout(greet('you'));\n");
+        write(root, "js/greet.js", "export function greet(name) { return `hello ${name}`; }\n");
+        write(root, "lib/shout.js", "import { punctuation } from '../lib/text/punctuation.js';\nexport function shout(text) { console.log(text.toUpperCase() + punctuation); }\n");
+        write(root, "lib/text/punctuation.js", "export const punctuation = '!';\n");
+        let bundled = bundle(&root.join("js/app.js"), root, &ModuleBundling::default()).unwrap();
+        assert!(!bundled.code.contains("import"), "{}", bundled.code);
+        assert!(bundled.code.contains("function greet(name)"), "{}", bundled.code);
+        assert!(bundled.code.contains("function shout(text)"), "{}", bundled.code);
+        assert!(bundled.code.contains("'!'"), "{}", bundled.code);
+        let mut inputs = ["js/app.js", "js/greet.js", "lib/shout.js", "lib/text/punctuation.js"].map(|path| root.join(path));
+        inputs.sort();
+        assert_eq!(bundled.inputs, inputs);
+    }
+
+    #[test]
+    fn syntax_errors_name_the_file_line_and_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "app.js", "import './broken.js';\n");
+        write(root, "broken.js", "const ok = 1;\n  let = ;\n");
+        let error = bundle(&root.join("app.js"), root, &ModuleBundling::default()).unwrap_err();
+        let location = format!("{}:2:9: ", root.join("broken.js").display());
+        assert!(error.contains(&location), "{error}");
+    }
+
+    #[test]
+    fn bundles_are_named_after_their_code() {
+        let root = Path::new("/site");
+        let output_dir = Path::new("/site/output");
+        let path = output_path(&root.join("js/app.js"), root, output_dir, "console.log(1)");
+        let digest = crate::build_cache::hex_digest(b"console.log(1)");
+        assert_eq!(path, output_dir.join(format!("_modules/js/app.{}.js", &digest[..8])));
+        assert_eq!(path, output_path(&root.join("js/app.js"), root, output_dir, "console.log(1)"));
+        assert_ne!(path, output_path(&root.join("js/app.js"), root, output_dir, "console.log(2)"));
+    }
+
+    #[test]
+    fn entries_with_bare_imports_are_published_as_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "index.html", "<script type=\"module\" src=\"app.js\"></script>");
+        write(root, "app.js", "import { html } from 'lit';\ndocument.body.append(html);\n");
+        let mut compiler = crate::compile::Compiler::new(root, root.join("output"));
+        compiler.input_paths = vec![crate::compile::InputRule::new(root.join("index.html"))];
+        compiler.pretty_print = false;
+        compiler.modules = Some(ModuleBundling::default());
+        let report = compiler.run().unwrap();
+        let warnings = report.diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].contains("bare import \"lit\""), "{warnings:?}");
+        assert!(!root.join("output").join(MODULE_OUTPUT_DIR).exists());
+        let published = std::fs::read_to_string(root.join("output/app.js")).unwrap();
+        assert_eq!(published, std::fs::read_to_string(root.join("app.js")).unwrap());
+        let page = std::fs::read_to_string(root.join("output/index.html")).unwrap();
+        assert!(page.contains("src=\"app.js\""), "{page}");
+    }
+}
@@ -0,0 +1,210 @@
+//! Differential checking of the parser backends.
+//!
+//! Every [`ParserBackend`] must turn the same source into the same [`Html`] tree; this
+//! parses a corpus of tricky html5lib-style inputs plus any given files with each
+//! backend and reports where they disagree.
+use std::path::PathBuf;
+
+use crate::html::{Html, ParserBackend, ParserMode};
+
+/// Inputs exercising the tree builder's error recovery (foster parenting, the adoption
+/// agency algorithm, foreign content, templates, …), modelled on html5lib's
+/// tree-construction tests.
+pub const TRICKY_CASES: &[(&str, &str)] = &[
+    ("foster-parented text", "<table>foo<tr><td>x</td></tr>bar</table>"),
+    ("foster-parented element", "<table><b>bold</b><tr><td>x</td></tr></table>"),
+    ("unclosed p around block", "<p>one<div>two</div>three"),
+    ("misnested formatting", "<b>1<p>2</b>3</p>"),
+    ("nested anchors", "<a><p>X<a>Y</a>Z</p></a>"),
+    ("reconstructed formatting", "<p><b><i><u>x</p>y"),
+    ("stray end tags", "<div></span>x</p>y</div>"),
+    ("implied list items", "<ul><li>one<li>two</ul>"),
+    ("implied options", "<select><option>a<option>b</select>"),
+    ("template rows", "<template><tr><td>a</td></tr></template>"),
+    ("svg in table cell", "<table><tr><td><svg><desc><td></desc><circle></svg></td></tr></table>"),
+    ("svg self-closing", "<svg width=\"10\"><rect width=\"10\" /></svg><p>after</p>"),
+    ("svg foreign object", "<svg><foreignObject><p>x</p></foreignObject></svg>"),
+    ("svg prefixed attribute", "<svg><use xlink:href=\"#icon\"/></svg>"),
+    ("mathml integration point", "<math><annotation-xml encoding=\"text/html\"><div>x</div></annotation-xml></math>"),
+    ("raw text", "<textarea>\n<b>not bold</b></textarea><script>if (a < b) {}</script>"),
+    ("comments and processing instructions", "<!-- comment --><p>after</p><?pi?>"),
+    ("hidden input in table", "<table><form><input type=hidden></form></table>"),
+    ("document with duplicate body", "<!DOCTYPE html><html><body x=1><body y=2>text"),
+    ("document head content after body", "<html><body><p>x</p></body><meta charset=utf-8></html>"),
+];
+
+// ————————————————————————————————————————————————————————————————————————————
+// REPORT
+// ————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    /// The case name or file path.
+    pub label: String,
+    /// The first line where the canonical trees differ, per backend.
+    pub difference: Vec<(ParserBackend, String)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+    pub fn print(&self) {
+        for mismatch in self.mismatches.iter() {
+            println!("❌ {}", mismatch.label);
+            for (backend, line) in mismatch.difference.iter() {
+                println!("    {backend:?}: {line}");
+            }
+        }
+        println!(
+            "checked {} inputs, {} mismatches",
+            self.checked,
+            self.mismatches.len(),
+        );
+    }
+}
+
+// ————————————————————————————————————————————————————————————————————————————
+// CHECKING
+// ————————————————————————————————————————————————————————————————————————————
+
+/// Checks the built-in corpus followed by every file in `files`.
+pub fn check_parsers(files: &[PathBuf]) -> CheckReport {
+    let mut report = CheckReport::default();
+    for (name, source) in TRICKY_CASES {
+        report.check(name, source, mode_for_source(source));
+    }
+    for path in files {
+        let label = path.display().to_string();
+        match std::fs::read_to_string(path) {
+            Ok(source) => report.check(&label, &source, mode_for_source(&source)),
+            Err(error) => eprintln!("⚠️ skipping {label}: {error}"),
+        }
+    }
+    report
+}
+
+impl CheckReport {
+    fn check(&mut self, label: &str, source: &str, mode: ParserMode) {
+        self.checked += 1;
+        let reference = canonical_lines(&Html::parse(source, mode.clone(), ParserBackend::Native));
+        let other = canonical_lines(&Html::parse(source, mode, ParserBackend::RcDom));
+        if reference == other {
+            return
+        }
+        let index = reference
+            .iter()
+            .zip(other.iter())
+            .position(|(left, right)| left != right)
+            .unwrap_or(reference.len().min(other.len()));
+        let line_at = |lines: &[String]| {
+            lines.get(index).cloned().unwrap_or_else(|| String::from("<end of tree>"))
+        };
+        self.mismatches.push(Mismatch {
+            label: label.to_string(),
+            difference: vec![
+                (ParserBackend::Native, line_at(&reference)),
+                (ParserBackend::RcDom, line_at(&other)),
+            ],
+        });
+    }
+}
+
+/// Templates are whole documents; everything else (pages, includes) is parsed the way
+/// the compiler parses it, as a fragment under a `<div>`.
+fn mode_for_source(source: &str) -> ParserMode {
+    let lowered = source.trim_start().to_ascii_lowercase();
+    if lowered.starts_with("<!doctype") || lowered.starts_with("<html") {
+        ParserMode::Document
+    } else {
+        ParserMode::fragment("div")
+    }
+}
+
+// ————————————————————————————————————————————————————————————————————————————
+// CANONICAL FORM
+// ————————————————————————————————————————————————————————————————————————————
+
+/// Renders a tree one node per line with sorted attributes, flattened fragments and
+/// merged adjacent text, so trees compare independently of incidental structure.
+fn canonical_lines(html: &Html) -> Vec<String> {
+    let mut lines = Vec::new();
+    render_nodes(&flatten(std::slice::from_ref(html)), 0, &mut lines);
+    lines
+}
+
+fn flatten(nodes: &[Html]) -> Vec<Html> {
+    let mut output = Vec::<Html>::new();
+    for node in nodes {
+        match node {
+            Html::Fragment(children) => output.extend(flatten(children)),
+            Html::Text(text) => match output.last_mut() {
+                Some(Html::Text(previous)) => previous.push_str(text),
+                _ => output.push(Html::Text(text.clone())),
+            },
+            Html::Element(_) => output.push(node.clone()),
+        }
+    }
+    output
+}
+
+fn render_nodes(nodes: &[Html], depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        match node {
+            Html::Text(text) => lines.push(format!("{indent}{text:?}")),
+            Html::Element(element) => {
+                let mut attrs = element.attrs
+                    .iter()
+                    .map(|(key, value)| format!(" {key}={value:?}"))
+                    .collect::<Vec<_>>();
+                attrs.sort();
+                lines.push(format!("{indent}<{}{}>", element.tag, attrs.concat()));
+                render_nodes(&flatten(&element.children), depth + 1, lines);
+            }
+            Html::Fragment(_) => unreachable!("fragments are flattened"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_agree(report: &CheckReport) {
+        let labels = report.mismatches
+            .iter()
+            .map(|mismatch| format!("{}: {:?}", mismatch.label, mismatch.difference))
+            .collect::<Vec<_>>();
+        assert!(report.is_ok(), "backends disagree on:\n{}", labels.join("\n"));
+    }
+
+    #[test]
+    fn backends_agree_on_tricky_cases() {
+        let report = check_parsers(&[]);
+        assert_eq!(report.checked, TRICKY_CASES.len());
+        assert_agree(&report);
+    }
+
+    #[test]
+    fn backends_agree_on_sample_pages() {
+        let sample = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../sample");
+        let output_dir = sample.join("output");
+        let pattern = sample.join("**/*.html").to_string_lossy().to_string();
+        let files = crate::path_utils::resolve_file_path_paterns(&[pattern])
+            .unwrap()
+            .into_iter()
+            .filter(|path| !path.starts_with(&output_dir))
+            .collect::<Vec<_>>();
+        assert!(!files.is_empty(), "no sample pages under {sample:?}");
+        let report = check_parsers(&files);
+        assert_eq!(report.checked, TRICKY_CASES.len() + files.len());
+        assert_agree(&report);
+    }
+}
@@ -1,14 +1,7 @@
 use std::path::{Path, PathBuf};
-use pathdiff::diff_paths;
 
 
 /// Converts a raw relative path in source HTML into a virtual path prefixed with `@/`
-///
-/// ### Example
-/// ```
-/// let virtual_path = normalize_virtual_path("pages/page1.html", "sample/main.html", "sample/");
-/// assert_eq!(virtual_path, "@/pages/page1.html");
-/// ```
 pub fn normalize_virtual_path(
     href: &str,
     origin_file_path: impl AsRef<Path>,
@@ -28,45 +21,7 @@ pub fn normalize_virtual_path(
 }
 
 
-/// Resolves a virtual path (starting with `@/`) into a relative path
-/// from the HTML file's output location to the target file.
-///
-/// ### Example
-/// ```
-/// let final_href = resolve_virtual_path("@/pages/page1.html", "output/pages/index.html");
-/// assert_eq!(final_href, "page1.html");
-/// ```
-pub fn resolve_virtual_path(
-    virtual_path: &str,
-    current_output_file: impl AsRef<Path>,
-) -> String {
-    if is_external_url(virtual_path) || !virtual_path.starts_with("@/") {
-        return virtual_path.to_string();
-    }
-
-    let logical_path = virtual_path.trim_start_matches("@/");
-    let target_file = Path::new("output").join(logical_path);
-    let current_dir = current_output_file.as_ref().parent().unwrap();
-
-    let resolved = pathdiff::diff_paths(&target_file, current_dir)
-        .unwrap_or_else(|| PathBuf::from(logical_path));
-    path_clean::clean(&resolved).to_string_lossy().replace('\\', "/")
-}
-
-
-
 /// Returns true if a link is an external URL and should not be rewritten.
-///
-/// # Example
-///
-/// ```rust
-/// use your_crate::path_utils::is_external_url;
-///
-/// assert!(is_external_url("https://example.com"));
-/// assert!(is_external_url("//cdn.example.com/lib.css"));
-/// assert!(is_external_url("mailto:hi@example.com"));
-/// assert!(!is_external_url("pages/page1.html"));
-/// ```
 pub fn is_external_url(href: &str) -> bool {
     let lowered = href.trim().to_ascii_lowercase();
     lowered.starts_with("http://")
@@ -74,6 +29,7 @@ pub fn is_external_url(href: &str) -> bool {
         || lowered.starts_with("//")
         || lowered.starts_with("mailto:")
         || lowered.starts_with("tel:")
+        || lowered.starts_with("data:")
         || lowered.starts_with("#")
 }
 
@@ -112,30 +68,22 @@ pub fn resolve_file_path_paterns(patterns: &[String]) -> Result<Vec<PathBuf>, Bo
     Ok(results)
 }
 
-/// Returns the common ancestor (shared prefix) of two paths, if any.
-pub fn common_ancestor(p1: impl AsRef<std::path::Path>, p2: impl AsRef<std::path::Path>) -> Option<PathBuf> {
-    use std::path::Component;
-    let p1 = p1.as_ref();
-    let p2 = p2.as_ref();
-    /// Converts a `Component` to a string slice
-    fn component_as_str<'a>(component: &'a Component) -> &'a std::ffi::OsStr {
-        component.as_os_str()
-    }
-    let mut result = PathBuf::new();
-    let mut p1_components = p1.components();
-    let mut p2_components = p2.components();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    loop {
-        match (p1_components.next(), p2_components.next()) {
-            (Some(c1), Some(c2)) if c1 == c2 => result.push(component_as_str(&c1)),
-            _ => break,
-        }
+    #[test]
+    fn virtual_paths_are_relative_to_the_project_root() {
+        let virtual_path = normalize_virtual_path("pages/page1.html", "sample/main.html", "sample/");
+        assert_eq!(virtual_path, "@/pages/page1.html");
     }
 
-    if result.as_os_str().is_empty() {
-        None
-    } else {
-        Some(result)
+    #[test]
+    fn recognizes_external_urls() {
+        assert!(is_external_url("https://example.com"));
+        assert!(is_external_url("//cdn.example.com/lib.css"));
+        assert!(is_external_url("mailto:hi@example.com"));
+        assert!(is_external_url("data:image/png;base64,iVBORw0KGgo="));
+        assert!(!is_external_url("pages/page1.html"));
     }
 }
-
@@ -0,0 +1,241 @@
+//! Removing files the build no longer produces.
+//!
+//! Every build records the outputs it produced (pages and asset symlinks) in the cache
+//! directory, apart from the build cache so builds that skip it still prune. Anything
+//! recorded last time but not produced this time is stale, as is any relative symlink
+//! in the output directory whose target is gone.
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+const OUTPUT_RECORD_FILE_NAME: &str = "outputs.json";
+
+/// The outputs recorded by the last build in `cache_dir`; empty when there's no
+/// readable record.
+pub fn load_output_record(cache_dir: impl AsRef<Path>) -> BTreeSet<PathBuf> {
+    std::fs::read_to_string(cache_dir.as_ref().join(OUTPUT_RECORD_FILE_NAME))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_output_record(cache_dir: impl AsRef<Path>, outputs: &BTreeSet<PathBuf>) -> std::io::Result<()> {
+    let cache_dir = cache_dir.as_ref();
+    std::fs::create_dir_all(cache_dir)?;
+    let text = serde_json::to_string(outputs).map_err(std::io::Error::other)?;
+    std::fs::write(cache_dir.join(OUTPUT_RECORD_FILE_NAME), text)
+}
+
+/// Outputs recorded by the previous build that the current one didn't produce, plus
+/// dangling symlinks.
+pub fn stale_outputs(
+    output_dir: &Path,
+    previous: &BTreeSet<PathBuf>,
+    current: &BTreeSet<PathBuf>,
+) -> Vec<PathBuf> {
+    let mut stale = previous
+        .difference(current)
+        .filter(|path| path.starts_with(output_dir) && path.symlink_metadata().is_ok())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    stale.extend(dangling_symlinks(output_dir));
+    stale.into_iter().collect()
+}
+
+/// Every file in `output_dir` that isn't in `produced` (the outputs of the last
+/// build), plus dangling symlinks. Symlinked directories aren't descended into.
+pub fn orphaned_outputs(output_dir: &Path, produced: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let mut orphaned = walk(output_dir)
+        .into_iter()
+        .filter(|path| !produced.contains(path))
+        .collect::<BTreeSet<_>>();
+    orphaned.extend(dangling_symlinks(output_dir));
+    orphaned.into_iter().collect()
+}
+
+/// Relative symlinks (the kind `create_relative_symlink` makes) whose target no
+/// longer exists.
+pub fn dangling_symlinks(output_dir: &Path) -> Vec<PathBuf> {
+    walk(output_dir)
+        .into_iter()
+        .filter(|path| {
+            let is_relative_link = std::fs::read_link(path)
+                .map(|target| target.is_relative())
+                .unwrap_or(false);
+            is_relative_link && !path.exists()
+        })
+        .collect()
+}
+
+/// Deletes `paths`, then any directories left empty, up to `output_dir`. Refuses,
+/// deleting nothing, when a path isn't inside `output_dir`.
+pub fn remove_outputs(output_dir: &Path, paths: &[PathBuf]) -> std::io::Result<()> {
+    let outside = paths.iter().find(|path| {
+        let path = path_clean::clean(path);
+        path == output_dir || !path.starts_with(output_dir)
+    });
+    if let Some(path) = outside {
+        let message = format!("refusing to remove {path:?}: it isn't inside {output_dir:?}");
+        return Err(std::io::Error::other(message))
+    }
+    for path in paths {
+        std::fs::remove_file(path)?;
+        let mut parent = path.parent();
+        while let Some(dir) = parent.filter(|dir| dir.starts_with(output_dir) && *dir != output_dir) {
+            let is_empty = std::fs::read_dir(dir)?.next().is_none();
+            if !is_empty {
+                break
+            }
+            std::fs::remove_dir(dir)?;
+            parent = dir.parent();
+        }
+    }
+    Ok(())
+}
+
+/// Wipes `output_dir`, refusing when it contains the project root.
+pub fn clean_output_dir(output_dir: &Path, project_root: &Path) -> std::io::Result<()> {
+    let output_dir = path_clean::clean(std::path::absolute(output_dir)?);
+    let project_root = path_clean::clean(std::path::absolute(project_root)?);
+    if project_root.starts_with(&output_dir) {
+        let message = format!("refusing to clean {output_dir:?}: it contains the project root");
+        return Err(std::io::Error::other(message))
+    }
+    match std::fs::remove_dir_all(&output_dir) {
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Files and symlinks under `dir`, recursively, without following symlinks.
+pub(crate) fn walk(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::default()
+    };
+    let mut files = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => files.extend(walk(&path)),
+            Ok(_) => files.push(path),
+            Err(_) => (),
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn stale_outputs_are_recorded_but_not_produced() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("output");
+        let kept = output_dir.join("index.html");
+        let stale = output_dir.join("old.html");
+        let already_gone = output_dir.join("gone.html");
+        let outside = dir.path().join("notes.txt");
+        for path in [&kept, &stale, &outside] {
+            write(path);
+        }
+        let previous = BTreeSet::from([kept.clone(), stale.clone(), already_gone, outside]);
+        let current = BTreeSet::from([kept]);
+        assert_eq!(stale_outputs(&output_dir, &previous, &current), vec![stale]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_symlinks_are_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("output");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let link = output_dir.join("logo.png");
+        std::os::unix::fs::symlink("../logo.png", &link).unwrap();
+        assert_eq!(stale_outputs(&output_dir, &BTreeSet::new(), &BTreeSet::new()), vec![link.clone()]);
+        write(&dir.path().join("logo.png"));
+        assert!(dangling_symlinks(&output_dir).is_empty());
+    }
+
+    #[test]
+    fn orphaned_outputs_are_files_not_produced() {
+        let dir = tempfile::tempdir().unwrap();
+        let produced = dir.path().join("index.html");
+        let orphan = dir.path().join("nested/orphan.html");
+        write(&produced);
+        write(&orphan);
+        assert_eq!(orphaned_outputs(dir.path(), &BTreeSet::from([produced])), vec![orphan]);
+    }
+
+    #[test]
+    fn remove_outputs_prunes_empty_directories_up_to_the_output_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("output");
+        let removed = output_dir.join("a/b/page.html");
+        let sibling = output_dir.join("a/other.html");
+        write(&removed);
+        write(&sibling);
+        remove_outputs(&output_dir, std::slice::from_ref(&removed)).unwrap();
+        assert!(!removed.exists());
+        assert!(!output_dir.join("a/b").exists());
+        assert!(sibling.exists());
+        remove_outputs(&output_dir, std::slice::from_ref(&sibling)).unwrap();
+        assert!(!output_dir.join("a").exists());
+        assert!(output_dir.is_dir());
+    }
+
+    #[test]
+    fn remove_outputs_refuses_paths_outside_the_output_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("output");
+        let inside = output_dir.join("index.html");
+        let source = dir.path().join("index.html");
+        let escaping = output_dir.join("../index.html");
+        write(&inside);
+        write(&source);
+        assert!(remove_outputs(&output_dir, &[inside.clone(), source.clone()]).is_err());
+        assert!(remove_outputs(&output_dir, &[escaping]).is_err());
+        assert!(remove_outputs(&output_dir, std::slice::from_ref(&output_dir)).is_err());
+        assert!(inside.exists());
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn clean_output_dir_refuses_to_delete_the_project_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_root = dir.path().join("site");
+        let page = project_root.join("index.html");
+        write(&page);
+        assert!(clean_output_dir(&project_root, &project_root).is_err());
+        assert!(clean_output_dir(dir.path(), &project_root).is_err());
+        assert!(clean_output_dir(&project_root.join(".."), &project_root).is_err());
+        assert!(page.exists());
+    }
+
+    #[test]
+    fn clean_output_dir_wipes_the_output_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_root = dir.path().join("site");
+        let output_dir = project_root.join("output");
+        write(&output_dir.join("a/index.html"));
+        write(&project_root.join("index.html"));
+        clean_output_dir(&output_dir, &project_root).unwrap();
+        assert!(!output_dir.exists());
+        assert!(project_root.join("index.html").exists());
+        // Already gone.
+        clean_output_dir(&output_dir, &project_root).unwrap();
+    }
+
+    #[test]
+    fn output_record_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_output_record(dir.path()).is_empty());
+        let outputs = BTreeSet::from([PathBuf::from("output/index.html")]);
+        save_output_record(dir.path(), &outputs).unwrap();
+        assert_eq!(load_output_record(dir.path()), outputs);
+    }
+}
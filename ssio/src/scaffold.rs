@@ -0,0 +1,117 @@
+//! `ssio init`: a starter project that builds out of the box.
+use std::path::{Path, PathBuf};
+
+use crate::compile::BuildReport;
+use crate::manifest::BuildOptions;
+
+/// Every file of the starter project, relative to its root.
+pub const STARTER_FILES: &[(&str, &str)] = &[
+    ("site.toml", SITE_TOML),
+    ("base.html", BASE_HTML),
+    ("navigation.html", NAVIGATION_HTML),
+    ("styles.css", STYLES_CSS),
+    ("pages/index.html", INDEX_HTML),
+    ("assets/logo.svg", LOGO_SVG),
+    (".gitignore", GITIGNORE),
+];
+
+const SITE_TOML: &str = r#"# The project root, relative to this file. Every other path is relative to it.
+root = "."
+
+# Where compiled pages and assets are written.
+output_dir = "output"
+
+# Every page is baked into this template at its `<content>` element.
+template = "base.html"
+
+# Pages to compile. Each match is written to the output directory at its path
+# relative to the project root, minus `strip_prefix` when given; so
+# `pages/about/team.html` becomes `output/about/team.html`.
+[[globs]]
+pattern = "pages/**/*.html"
+strip_prefix = "pages/"
+
+# Directories published as a whole (symlinked into the output directory)
+# rather than file by file as pages reference them.
+[[bundles]]
+location = "assets"
+"#;
+
+const BASE_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>My Site</title>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <link rel="stylesheet" href="styles.css">
+</head>
+<body>
+    <include src="navigation.html"></include>
+    <main>
+        <content></content>
+    </main>
+</body>
+</html>
+"#;
+
+const NAVIGATION_HTML: &str = r#"<nav>
+    <a href="pages/index.html"><img src="assets/logo.svg" alt="Home"></a>
+</nav>
+"#;
+
+const STYLES_CSS: &str = r#"body {
+    margin: 0 auto;
+    max-width: 48rem;
+    padding: 0 1rem;
+    font-family: system-ui, sans-serif;
+}
+
+nav img {
+    height: 2.5rem;
+}
+"#;
+
+const INDEX_HTML: &str = r#"<h1>Hello!</h1>
+<p>Edit <code>pages/index.html</code> and run <code>ssio build --manifest site.toml</code>.</p>
+"#;
+
+const LOGO_SVG: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 32 32"><circle cx="16" cy="16" r="14" fill="#4a6cf7"/></svg>
+"##;
+
+const GITIGNORE: &str = "output/\n.ssio-cache/\n";
+
+/// Writes the starter project into `dir`, refusing to overwrite anything; returns
+/// the files it created.
+pub fn init_project(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let dir = dir.as_ref();
+    let existing = STARTER_FILES
+        .iter()
+        .map(|(path, _)| dir.join(path))
+        .filter(|path| path.exists())
+        .collect::<Vec<_>>();
+    if !existing.is_empty() {
+        return Err(format!("refusing to overwrite existing files: {existing:?}").into())
+    }
+    let mut created = Vec::with_capacity(STARTER_FILES.len());
+    for (path, contents) in STARTER_FILES {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, contents)?;
+        created.push(path);
+    }
+    Ok(created)
+}
+
+/// Builds a freshly initialized project, failing if it doesn't build cleanly.
+pub fn verify_project(dir: impl AsRef<Path>) -> Result<BuildReport, Box<dyn std::error::Error>> {
+    let dir = dir.as_ref();
+    let manifest = crate::manifest::load_project_manifest(dir.join("site.toml"))?;
+    let report = manifest.execute(dir, &BuildOptions::default())?;
+    if !report.diagnostics.is_empty() {
+        report.emit_diagnostics();
+        return Err("the starter project didn't build cleanly".into())
+    }
+    Ok(report)
+}
@@ -1,11 +1,5 @@
 use std::fs;
-use std::path::{Path, PathBuf};
-
-#[cfg(unix)]
-use std::os::unix::fs::symlink;
-
-#[cfg(windows)]
-use std::os::windows::fs::symlink_file;
+use std::path::Path;
 
 #[cfg(unix)]
 use std::os::unix::fs as unix_fs;
@@ -17,35 +11,6 @@ use pathdiff::diff_paths;
 
 const DEBUG_MODE: bool = false;
 
-/// Create a symbolic link at `link_path` pointing to `target_path`.
-/// Handles platform differences and ensures parent directories exist.
-pub fn create_symlink<P: AsRef<Path>, Q: AsRef<Path>>(target_path: P, link_path: Q) -> std::io::Result<()> {
-    let target_path = target_path.as_ref();
-    let link_path = link_path.as_ref();
-
-    // Ensure parent directory exists
-    if let Some(parent) = link_path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
-    // Remove existing file or link
-    if link_path.exists() {
-        fs::remove_file(link_path)?;
-    }
-
-    #[cfg(unix)]
-    {
-        symlink(target_path, link_path)?;
-    }
-
-    #[cfg(windows)]
-    {
-        symlink_file(target_path, link_path)?;
-    }
-
-    Ok(())
-}
-
 /// Create a symbolic link at `link_path` pointing to `source_path`; only if needed.
 /// 
 /// Handles platform differences and ensures parent directories exist.
@@ -54,7 +54,7 @@ impl Element {
             .map(|x| x.bake_template_content(aggregator, content, is_baked))
             .collect::<Vec<_>>();
         return State::flatten(children, Some(children_len)).map(|xs| {
-            Html::Element(Element { tag: self.tag, attrs: self.attrs, children: xs })
+            Html::Element(Element { tag: self.tag, attrs: self.attrs, children: xs, location: self.location })
         })
     }
 }